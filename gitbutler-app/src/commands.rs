@@ -169,3 +169,47 @@ pub async fn project_flush_and_push(handle: tauri::AppHandle, id: &str) -> Resul
 
     Ok(())
 }
+
+/// Clears the project's repository lock if the process it was taken for is not running anymore,
+/// returning the pid of that process, or `None` if there was none. A lock still held, even on
+/// behalf of such a process, is not broken.
+#[tauri::command(async)]
+#[instrument(skip(handle))]
+pub async fn project_break_stale_lock(
+    handle: tauri::AppHandle,
+    id: &str,
+) -> Result<Option<u32>, Error> {
+    let project_id = id.parse().map_err(|_| Error::UserError {
+        code: Code::Validation,
+        message: "Malformed project id".into(),
+    })?;
+
+    let users = handle.state::<users::Controller>().inner().clone();
+    let projects = handle.state::<projects::Controller>().inner().clone();
+    let local_data_dir = handle
+        .path_resolver()
+        .app_data_dir()
+        .context("failed to get app data dir")?;
+
+    let project = projects.get(&project_id).context("failed to get project")?;
+    let user = users.get_user()?;
+    let project_repository = project_repository::Repository::open(&project)?;
+    let gb_repo =
+        gb_repository::Repository::open(&local_data_dir, &project_repository, user.as_ref())
+            .context("failed to open repository")?;
+
+    match gb_repo.break_stale_lock() {
+        Ok(owner_pid) => Ok(owner_pid),
+        Err(gb_repository::LockError::StaleLock { owner_pid }) => Err(Error::UserError {
+            code: Code::Projects,
+            message: format!(
+                "The project is locked on behalf of process {owner_pid}, which is not running. A process it started still holds the lock"
+            ),
+        }),
+        Err(gb_repository::LockError::Held { .. }) => Err(Error::UserError {
+            code: Code::Projects,
+            message: "The project is locked by a running process".into(),
+        }),
+        Err(error) => Err(anyhow::Error::from(error).into()),
+    }
+}
@@ -16,7 +16,7 @@ use fslock::LockFile;
 use sha2::{Digest, Sha256};
 
 use crate::{
-    deltas, fs, git, project_repository,
+    deltas, fs, git, keys, project_repository,
     projects::{self, ProjectId},
     reader, sessions,
     sessions::SessionId,
@@ -24,10 +24,20 @@ use crate::{
     virtual_branches::{self, target},
 };
 
+// how often `lock_with_timeout` checks whether the lock was released.
+const LOCK_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
 pub struct Repository {
     git_repository: git::Repository,
     project: projects::Project,
     lock_path: path::PathBuf,
+    // the session writes are scoped to by `with_session`, if any.
+    scoped_session: std::sync::Mutex<Option<SessionId>>,
+    // the key target urls are encrypted with, if the project asks for it.
+    target_encryption_key: Option<target::EncryptionKey>,
+    // the directory `open_in_memory` keeps the data in, removed once the repository is dropped.
+    #[cfg(test)]
+    temp_dir: Option<tempfile::TempDir>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,6 +59,31 @@ impl Repository {
         root: &path::Path,
         project_repository: &project_repository::Repository,
         user: Option<&users::User>,
+    ) -> Result<Self, Error> {
+        Self::open_with(root, project_repository, user, false)
+    }
+
+    /// Opens a repository that keeps its data in a temporary directory, removed when the
+    /// repository is dropped, and the git objects it writes, e.g. when flushing sessions, in
+    /// memory only. Locking and sessions work as they do for repositories opened with `open`,
+    /// so tests can use it where persisting the data is of no use.
+    #[cfg(test)]
+    pub fn open_in_memory(
+        project_repository: &project_repository::Repository,
+    ) -> Result<Self, Error> {
+        let temp_dir = tempfile::tempdir().context("failed to create temporary directory")?;
+        let repository = Self::open_with(temp_dir.path(), project_repository, None, true)?;
+        Ok(Self {
+            temp_dir: Some(temp_dir),
+            ..repository
+        })
+    }
+
+    fn open_with(
+        root: &path::Path,
+        project_repository: &project_repository::Repository,
+        user: Option<&users::User>,
+        in_memory: bool,
     ) -> Result<Self, Error> {
         let project = project_repository.project();
         let project_objects_path = project.path.join(".git/objects");
@@ -56,6 +91,16 @@ impl Repository {
             return Err(Error::ProjectPathNotFound(project_objects_path));
         }
 
+        let target_encryption_key = if project.encrypt_target_urls.unwrap_or(false) {
+            let key = keys::Controller::try_from(&root.to_path_buf())
+                .context("failed to open keys")?
+                .get_or_create_target_key()
+                .context("failed to get target key")?;
+            Some(target::EncryptionKey::new(key))
+        } else {
+            None
+        };
+
         let projects_dir = root.join("projects");
 
         let path = projects_dir.join(project.id.to_string());
@@ -68,11 +113,20 @@ impl Repository {
             git_repository
                 .add_disk_alternate(project_objects_path.to_str().unwrap())
                 .context("failed to add disk alternate")?;
+            if in_memory {
+                git_repository
+                    .add_mempack_backend()
+                    .context("failed to add in-memory object database")?;
+            }
 
             Result::Ok(Self {
                 git_repository,
                 project: project.clone(),
                 lock_path,
+                scoped_session: std::sync::Mutex::default(),
+                target_encryption_key,
+                #[cfg(test)]
+                temp_dir: None,
             })
         } else {
             let git_repository = git::Repository::init_opts(
@@ -87,11 +141,20 @@ impl Repository {
             git_repository
                 .add_disk_alternate(project_objects_path.to_str().unwrap())
                 .context("failed to add disk alternate")?;
+            if in_memory {
+                git_repository
+                    .add_mempack_backend()
+                    .context("failed to add in-memory object database")?;
+            }
 
             let gb_repository = Self {
                 git_repository,
                 project: project.clone(),
                 lock_path,
+                scoped_session: std::sync::Mutex::default(),
+                target_encryption_key,
+                #[cfg(test)]
+                temp_dir: None,
             };
 
             let _lock = gb_repository.lock();
@@ -357,14 +420,121 @@ impl Repository {
 
     pub fn lock(&self) -> LockFile {
         let mut lockfile = LockFile::open(&self.lock_path).expect("failed to open lock file");
-        lockfile.lock().expect("failed to obtain lock on lock file");
+        lockfile
+            .lock_with_pid()
+            .expect("failed to obtain lock on lock file");
         lockfile
     }
 
-    pub fn mark_active_session(&self) -> Result<()> {
-        let current_session = self
-            .get_or_create_current_session()
+    /// Like `lock`, but gives up once `timeout` has passed. If the lock is then held on behalf
+    /// of a process that is not running anymore, e.g. by a child it spawned that inherited the
+    /// lock, it's reported as `LockError::StaleLock`.
+    pub fn lock_with_timeout(&self, timeout: time::Duration) -> Result<LockFile, LockError> {
+        let mut lockfile = LockFile::open(&self.lock_path).context("failed to open lock file")?;
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            if lockfile
+                .try_lock_with_pid()
+                .context("failed to obtain lock on lock file")?
+            {
+                return Ok(lockfile);
+            }
+            let now = time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+        }
+
+        match self.lock_owner_pid() {
+            Some(owner_pid) if !is_process_running(owner_pid) => {
+                Err(LockError::StaleLock { owner_pid })
+            }
+            owner_pid => Err(LockError::Timeout { owner_pid }),
+        }
+    }
+
+    /// Clears the pid a process that is not running anymore left in the lock file, e.g. when
+    /// the user asks to from the UI, returning that pid, or `None` if there was none. This is
+    /// only done once the lock is taken: while the lock is still held, e.g. by a child the
+    /// process spawned that inherited it, nothing is broken and it fails with
+    /// `LockError::StaleLock`, so that the user can be told which process to look for. A lock
+    /// held by a running process fails with `LockError::Held`.
+    pub fn break_stale_lock(&self) -> Result<Option<u32>, LockError> {
+        // read before taking the lock, as that records the pid of this process in its place.
+        let recorded_pid = self.lock_owner_pid();
+        if self.try_lock()?.is_some() {
+            let stale_pid = recorded_pid.filter(|pid| !is_process_running(*pid));
+            if let Some(owner_pid) = stale_pid {
+                tracing::warn!(
+                    project_id = %self.project.id,
+                    owner_pid,
+                    "cleared stale repository lock"
+                );
+            }
+            return Ok(stale_pid);
+        }
+        match self.lock_owner_pid() {
+            Some(owner_pid) if !is_process_running(owner_pid) => {
+                Err(LockError::StaleLock { owner_pid })
+            }
+            owner_pid => Err(LockError::Held { owner_pid }),
+        }
+    }
+
+    // the pid the lock file records, if it's held.
+    fn lock_owner_pid(&self) -> Option<u32> {
+        std::fs::read_to_string(&self.lock_path)
+            .ok()
+            .and_then(|content| content.trim().parse::<u32>().ok())
+    }
+
+    /// Like `lock`, but returns `None` instead of waiting if the lock is held by someone else.
+    pub fn try_lock(&self) -> Result<Option<LockFile>> {
+        let mut lockfile = LockFile::open(&self.lock_path).context("failed to open lock file")?;
+        if lockfile
+            .try_lock_with_pid()
+            .context("failed to obtain lock on lock file")?
+        {
+            Ok(Some(lockfile))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn mark_active_session(&self) -> Result<sessions::Session> {
+        self.mark_active_session_reporting()
+            .map(|(session, _created)| session)
+    }
+
+    /// Like `mark_active_session`, but also tells whether the session had to be created. Both
+    /// the branch and the target writers mark sessions active through this, so that they
+    /// report creating sessions alike.
+    pub fn mark_active_session_reporting(&self) -> Result<(sessions::Session, bool)> {
+        self.mark_active_session_locked(self.lock())
+    }
+
+    /// Like `mark_active_session_reporting`, but returns `None` instead of waiting if the lock
+    /// is held by someone else. The lock taken is the one the session is looked up under, so
+    /// it can't be taken by someone else in between.
+    pub fn try_mark_active_session_reporting(&self) -> Result<Option<(sessions::Session, bool)>> {
+        self.try_lock()?
+            .map(|lock| self.mark_active_session_locked(lock))
+            .transpose()
+    }
+
+    fn mark_active_session_locked(&self, lock: LockFile) -> Result<(sessions::Session, bool)> {
+        let (current_session, created) = self
+            .get_or_create_current_session_locked(lock)
             .context("failed to get current session")?;
+        if let Some(scoped_session_id) = *self.scoped_session() {
+            if current_session.id != scoped_session_id {
+                return Err(anyhow!(
+                    "session {} ended while writes were scoped to it",
+                    scoped_session_id
+                ));
+            }
+        }
 
         let updated_session = sessions::Session {
             meta: sessions::Meta {
@@ -382,7 +552,44 @@ impl Repository {
             .write(&updated_session)
             .context("failed to write session")?;
 
-        Ok(())
+        Ok((updated_session, created))
+    }
+
+    /// Runs `action` with all writes through this repository, such as those of branch and
+    /// target writers, going to one session, which is flushed once `action` succeeds. Writes
+    /// fail rather than start a new session if the session ends before that, e.g. because
+    /// another process flushed it.
+    pub fn with_session<T>(
+        &self,
+        project_repository: &project_repository::Repository,
+        user: Option<&users::User>,
+        action: impl FnOnce(&sessions::Session) -> Result<T>,
+    ) -> Result<T> {
+        let session = self
+            .mark_active_session()
+            .context("failed to get or create current session")?;
+
+        let previous = self.scoped_session().replace(session.id);
+        let result = action(&session);
+        *self.scoped_session() = previous;
+        let result = result?;
+
+        // flushed as it is now, with the last time it was marked active
+        let session = self
+            .get_current_session()
+            .context("failed to get current session")?
+            .filter(|current| current.id == session.id)
+            .with_context(|| format!("session {} ended before it was flushed", session.id))?;
+        self.flush_session(project_repository, &session, user)
+            .with_context(|| format!("failed to flush session {}", session.id))?;
+
+        Ok(result)
+    }
+
+    fn scoped_session(&self) -> std::sync::MutexGuard<'_, Option<SessionId>> {
+        self.scoped_session
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
     }
 
     pub fn get_latest_session(&self) -> Result<Option<sessions::Session>> {
@@ -398,20 +605,32 @@ impl Repository {
     }
 
     pub fn get_or_create_current_session(&self) -> Result<sessions::Session> {
-        let _lock = self.lock();
+        self.get_or_create_current_session_reporting()
+            .map(|(session, _created)| session)
+    }
+
+    fn get_or_create_current_session_reporting(&self) -> Result<(sessions::Session, bool)> {
+        self.get_or_create_current_session_locked(self.lock())
+    }
 
+    // like `get_or_create_current_session_reporting`, under the given lock, which is released
+    // once the session is found or created.
+    fn get_or_create_current_session_locked(
+        &self,
+        lock: LockFile,
+    ) -> Result<(sessions::Session, bool)> {
         let reader = reader::Reader::open(&self.root())?;
         match sessions::Session::try_from(&reader) {
-            Result::Ok(session) => Ok(session),
+            Result::Ok(session) => Ok((session, false)),
             Err(sessions::SessionError::NoSession) => {
                 let project_repository = project_repository::Repository::open(&self.project)
                     .context("failed to open project repository")?;
                 let session = self
                     .create_current_session(&project_repository)
                     .context("failed to create current session")?;
-                drop(_lock);
+                drop(lock);
                 self.copy_branches().context("failed to unpack branches")?;
-                Ok(session)
+                Ok((session, true))
             }
             Err(err) => Err(err).context("failed to read current session"),
         }
@@ -516,6 +735,16 @@ impl Repository {
         self.git_repository.path().join("gitbutler")
     }
 
+    /// The key target urls are encrypted with, if the project encrypts them, see
+    /// `Project::encrypt_target_urls`.
+    pub fn target_encryption_key(&self) -> Option<&target::EncryptionKey> {
+        self.target_encryption_key.as_ref()
+    }
+
+    pub(crate) fn lock_path(&self) -> &path::Path {
+        &self.lock_path
+    }
+
     pub(crate) fn session_path(&self) -> std::path::PathBuf {
         self.root().join("session")
     }
@@ -524,18 +753,31 @@ impl Repository {
         self.session_path().join("wd")
     }
 
+    /// The default target, read from the latest session. If there is none, and the project
+    /// stores targets in the repository's git config, it's read from there instead, e.g. when
+    /// the gitbutler data was set up anew for a repository that already had its target.
     pub fn default_target(&self) -> Result<Option<target::Target>> {
         if let Some(latest_session) = self.get_latest_session()? {
             let latest_session_reader = sessions::Reader::open(self, &latest_session)
                 .context("failed to open current session")?;
             let target_reader = target::Reader::new(&latest_session_reader);
             match target_reader.read_default() {
-                Result::Ok(target) => Ok(Some(target)),
-                Err(reader::Error::NotFound) => Ok(None),
-                Err(err) => Err(err.into()),
+                Result::Ok(target) => return Ok(Some(target)),
+                Err(reader::Error::NotFound) => {}
+                Err(err) => return Err(err.into()),
             }
-        } else {
-            Ok(None)
+        }
+
+        if !self.project.store_targets_in_git_config.unwrap_or(false) {
+            return Ok(None);
+        }
+        let project_repository = project_repository::Repository::open(&self.project)
+            .context("failed to open project repository")?;
+        match target::GitConfigTargetWriter::new(&project_repository.git_repository).read_default()
+        {
+            Result::Ok(target) => Ok(Some(target)),
+            Err(reader::Error::NotFound) => Ok(None),
+            Err(err) => Err(err.into()),
         }
     }
 
@@ -954,6 +1196,18 @@ fn write_gb_commit(
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    #[error("timed out waiting for the repository lock")]
+    Timeout { owner_pid: Option<u32> },
+    #[error("repository is locked on behalf of process {owner_pid}, which is not running")]
+    StaleLock { owner_pid: u32 },
+    #[error("repository is locked by a running process")]
+    Held { owner_pid: Option<u32> },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RemoteError {
     #[error("network error")]
@@ -962,6 +1216,27 @@ pub enum RemoteError {
     Other(#[from] anyhow::Error),
 }
 
+// the lock file records the pid of its owner, which can't be checked for on every platform,
+// so where it can't, the owner is assumed to be running.
+#[cfg(target_family = "unix")]
+fn is_process_running(pid: u32) -> bool {
+    let pid = match i32::try_from(pid) {
+        Ok(pid) => nix::unistd::Pid::from_raw(pid),
+        Err(_) => return true,
+    };
+    // sending no signal only checks that the process exists. Processes of other users can't
+    // be signalled, failing with EPERM, but they are running all the same.
+    !matches!(
+        nix::sys::signal::kill(pid, None),
+        Err(nix::errno::Errno::ESRCH)
+    )
+}
+
+#[cfg(not(target_family = "unix"))]
+fn is_process_running(_pid: u32) -> bool {
+    true
+}
+
 #[cfg(test)]
 mod test {
     use anyhow::Result;
@@ -969,6 +1244,8 @@ mod test {
 
     use crate::test_utils::{Case, Suite};
 
+    use super::LockError;
+
     #[test]
     fn test_alternates_file_being_set() -> Result<()> {
         let Case {
@@ -994,4 +1271,39 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_break_stale_lock() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        assert_eq!(gb_repository.break_stale_lock()?, None);
+
+        // the lock is held by this process, which is running
+        let lock = gb_repository.lock();
+        assert!(matches!(
+            gb_repository.break_stale_lock(),
+            Err(LockError::Held { owner_pid: Some(owner_pid) }) if owner_pid == std::process::id()
+        ));
+
+        // the lock is still held, on behalf of a process that doesn't exist, as pids don't go
+        // that high. it's not broken.
+        std::fs::write(gb_repository.lock_path(), "999999999")?;
+        assert!(matches!(
+            gb_repository.break_stale_lock(),
+            Err(LockError::StaleLock {
+                owner_pid: 999_999_999
+            })
+        ));
+        assert!(gb_repository.try_lock()?.is_none());
+        drop(lock);
+
+        // the process died holding the lock, which released it but left its pid behind
+        std::fs::write(gb_repository.lock_path(), "999999999")?;
+        assert_eq!(gb_repository.break_stale_lock()?, Some(999_999_999));
+        assert_ne!(gb_repository.lock_owner_pid(), Some(999_999_999));
+        assert!(gb_repository.try_lock()?.is_some());
+
+        Ok(())
+    }
 }
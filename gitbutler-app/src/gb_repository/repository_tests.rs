@@ -9,6 +9,7 @@ use crate::{
     reader,
     sessions::{self, SessionId},
     test_utils::{Case, Suite},
+    virtual_branches,
 };
 
 fn test_remote_repository() -> Result<git2::Repository> {
@@ -91,6 +92,71 @@ fn test_must_flush_current_session() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_with_session() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    let mut branch = virtual_branches::branch::Branch {
+        id: virtual_branches::BranchId::generate(),
+        name: "branch".to_string(),
+        notes: String::new(),
+        applied: true,
+        upstream: None,
+        upstream_head: None,
+        created_timestamp_ms: 0,
+        updated_timestamp_ms: 0,
+        head: "0123456789abcdef0123456789abcdef01234567".parse()?,
+        tree: "0123456789abcdef0123456789abcdef01234567".parse()?,
+        ownership: virtual_branches::branch::Ownership::default(),
+        order: 0,
+        selected_for_changes: None,
+    };
+    let target = virtual_branches::target::Target {
+        branch: "refs/remotes/origin/master".parse()?,
+        remote_url: "remote url".to_string(),
+        sha: "0123456789abcdef0123456789abcdef01234567".parse()?,
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: virtual_branches::target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
+    };
+
+    let session_id = gb_repository.with_session(&project_repository, None, |session| {
+        virtual_branches::branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+        let outcome = virtual_branches::target::Writer::new(&gb_repository)?
+            .write(&branch.id, &target)?
+            .unwrap();
+        assert_eq!(outcome.session_id, session.id);
+        Ok(session.id)
+    })?;
+
+    // flushed at the end of the scope, with both the branch and its target
+    assert!(gb_repository.get_current_session()?.is_none());
+    let flushed = gb_repository.get_sessions_iterator()?.next().unwrap()?;
+    assert_eq!(flushed.id, session_id);
+    let session_reader = sessions::Reader::open(&gb_repository, &flushed)?;
+    assert_eq!(
+        virtual_branches::branch::Reader::new(&session_reader).read(&branch.id)?,
+        branch
+    );
+    assert_eq!(
+        virtual_branches::target::Reader::new(&session_reader).read(&branch.id)?,
+        target
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_list_deltas_from_current_session() -> Result<()> {
     let Case { gb_repository, .. } = Suite::default().new_case();
@@ -421,3 +487,55 @@ fn test_gitbutler_file() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_default_target_from_git_config() -> Result<()> {
+    let suite = Suite::default();
+    let Case {
+        project,
+        project_repository,
+        ..
+    } = suite.new_case();
+
+    let target = virtual_branches::target::Target {
+        branch: "refs/remotes/origin/master".parse()?,
+        remote_url: "remote url".to_string(),
+        sha: "0123456789abcdef0123456789abcdef01234567".parse()?,
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: virtual_branches::target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
+    };
+    virtual_branches::target::GitConfigTargetWriter::new(&project_repository.git_repository)
+        .write_default(&target)?;
+
+    // not read from the git config unless the project stores targets there
+    let gb_repository =
+        crate::gb_repository::Repository::open(&suite.local_app_data, &project_repository, None)?;
+    assert!(gb_repository.default_target()?.is_none());
+
+    let project = projects::Project {
+        store_targets_in_git_config: Some(true),
+        ..project
+    };
+    let project_repository = crate::project_repository::Repository::open(&project)?;
+    let gb_repository =
+        crate::gb_repository::Repository::open(&suite.local_app_data, &project_repository, None)?;
+    assert_eq!(gb_repository.default_target()?, Some(target.clone()));
+
+    // the target in the gitbutler data comes first
+    let own_target = virtual_branches::target::Target {
+        behind: 1,
+        ..target
+    };
+    virtual_branches::target::Writer::new(&gb_repository)?.write_default(&own_target)?;
+    assert_eq!(gb_repository.default_target()?, Some(own_target));
+
+    Ok(())
+}
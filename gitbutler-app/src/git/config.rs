@@ -17,6 +17,11 @@ impl From<Config> for git2::Config {
 }
 
 impl Config {
+    /// Opens the config file at `path` on its own, without the configs of other levels.
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        git2::Config::open(path).map(Into::into).map_err(Into::into)
+    }
+
     pub fn set_str(&mut self, key: &str, value: &str) -> Result<()> {
         self.config.set_str(key, value).map_err(Into::into)
     }
@@ -39,6 +44,14 @@ impl Config {
         }
     }
 
+    /// Removes the key, if it is set.
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        match self.config.remove(key).map_err(Into::into) {
+            Ok(()) | Err(Error::NotFound(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
         match self.config.get_bool(key).map_err(Into::into) {
             Ok(value) => Ok(Some(value)),
@@ -29,6 +29,19 @@ impl<'repo> Remote<'repo> {
         Ok(self.inner.url())
     }
 
+    /// Connects to the remote to list the names of the references it advertises.
+    pub fn ls(&mut self) -> Result<Vec<String>> {
+        self.inner.connect(git2::Direction::Fetch)?;
+        let names = self
+            .inner
+            .list()?
+            .iter()
+            .map(|head| head.name().to_string())
+            .collect();
+        self.inner.disconnect()?;
+        Ok(names)
+    }
+
     pub fn push(
         &mut self,
         refspec: &[&str],
@@ -60,6 +60,16 @@ impl Repository {
         Ok(())
     }
 
+    /// Makes the objects written from now on only be kept in memory, so they are lost once
+    /// the repository is dropped. Objects already on disk can still be read.
+    pub fn add_mempack_backend(&self) -> Result<()> {
+        // above the priorities of the loose and packed object backends, so it's written to.
+        const MEMPACK_PRIORITY: i32 = 1000;
+        let odb = self.0.odb()?;
+        odb.add_new_mempack_backend(MEMPACK_PRIORITY)?;
+        Ok(())
+    }
+
     pub fn add_submodule(&self, url: &Url, path: &path::Path) -> Result<Submodule<'_>> {
         self.0
             .submodule(&url.to_string(), path, false)
@@ -189,6 +199,27 @@ impl Repository {
             .map_err(Into::into)
     }
 
+    /// Returns the signature of the commit along with the data it signs, or `None` if the
+    /// commit is not signed.
+    pub fn extract_signature(&self, id: Oid) -> Result<Option<(String, Vec<u8>)>> {
+        match self.0.extract_signature(&id.into(), None) {
+            Ok((signature, signed_data)) => Ok(Some((
+                str::from_utf8(&signature)?.to_string(),
+                signed_data.to_vec(),
+            ))),
+            Err(error) if error.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Finds the commit whose id starts with the given, possibly abbreviated, hex sha.
+    pub fn find_commit_by_prefix(&self, prefix: &str) -> Result<Commit> {
+        self.0
+            .find_commit_by_prefix(prefix)
+            .map(Commit::from)
+            .map_err(Into::into)
+    }
+
     pub fn find_blob(&self, id: Oid) -> Result<Blob> {
         self.0
             .find_blob(id.into())
@@ -298,6 +329,15 @@ impl Repository {
         self.0.config().map(Into::into).map_err(Into::into)
     }
 
+    /// The repository's own config, i.e. `.git/config`, without the global and system ones.
+    pub fn local_config(&self) -> Result<Config> {
+        self.0
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+            .map(Into::into)
+            .map_err(Into::into)
+    }
+
     pub fn treebuilder<'repo>(&'repo self, tree: Option<&'repo Tree>) -> TreeBuilder<'repo> {
         TreeBuilder::new(self, tree)
     }
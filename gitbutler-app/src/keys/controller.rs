@@ -1,4 +1,5 @@
 use anyhow::Context;
+use rand::{rngs::OsRng, RngCore};
 use tauri::{AppHandle, Manager};
 
 use super::{storage::Storage, PrivateKey};
@@ -44,6 +45,25 @@ impl Controller {
             Ok(key)
         }
     }
+
+    /// The key the urls of targets are encrypted with at rest, for projects that ask for it,
+    /// see `Project::encrypt_target_urls`. It's generated the first time it's asked for.
+    pub fn get_or_create_target_key(&self) -> Result<[u8; 32], GetOrCreateError> {
+        if let Some(key) = self
+            .storage
+            .get_target_key()
+            .context("failed to get target key")?
+        {
+            Ok(key)
+        } else {
+            let mut key = [0; 32];
+            OsRng.fill_bytes(&mut key);
+            self.storage
+                .create_target_key(&key)
+                .context("failed to save target key")?;
+            Ok(key)
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -79,4 +99,19 @@ mod tests {
         let perms = format!("{:o}", permissions.mode());
         assert_eq!(perms, "100600");
     }
+
+    #[test]
+    fn test_get_or_create_target_key() {
+        let suite = Suite::default();
+        let controller = Controller::try_from(&suite.local_app_data).unwrap();
+
+        let once = controller.get_or_create_target_key().unwrap();
+        let twice = controller.get_or_create_target_key().unwrap();
+        assert_eq!(once, twice);
+
+        let permissions = fs::metadata(suite.local_app_data.join("keys/targets"))
+            .unwrap()
+            .permissions();
+        assert_eq!(format!("{:o}", permissions.mode()), "100600");
+    }
 }
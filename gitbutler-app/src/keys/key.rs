@@ -30,6 +30,14 @@ impl PrivateKey {
     }
 }
 
+/// Checks an ssh signature made in the `git` namespace, as by `PrivateKey::sign`, against the
+/// public key it carries. This tells whether `bytes` are what was signed, not who signed them.
+pub fn verify(signature: &str, bytes: &[u8]) -> Result<bool, SignError> {
+    let sig = SshSig::from_pem(signature)?;
+    let public_key = ssh_key::PublicKey::from(sig.public_key().clone());
+    Ok(public_key.verify("git", bytes, &sig).is_ok())
+}
+
 impl Default for PrivateKey {
     fn default() -> Self {
         let ed25519_keypair = ssh_key::private::Ed25519Keypair::random(&mut OsRng);
@@ -148,6 +156,15 @@ mod tests {
         assert_eq!(public_key, deserialized);
     }
 
+    #[test]
+    fn test_verify() {
+        let private_key = PrivateKey::generate();
+        let signature = private_key.sign(b"signed").unwrap();
+        assert!(verify(&signature, b"signed").unwrap());
+        assert!(!verify(&signature, b"tampered").unwrap());
+        verify("not a signature", b"signed").unwrap_err();
+    }
+
     #[test]
     fn test_serde_private() {
         let private_key = PrivateKey::generate();
@@ -1,3 +1,4 @@
+use base64::Engine;
 use tauri::{AppHandle, Manager};
 
 use crate::storage;
@@ -15,6 +16,8 @@ pub enum Error {
     Storage(#[from] storage::Error),
     #[error("SSH key error: {0}")]
     SSHKey(#[from] ssh_key::Error),
+    #[error("malformed target key")]
+    MalformedTargetKey,
 }
 
 impl TryFrom<&AppHandle> for Storage {
@@ -60,4 +63,27 @@ impl Storage {
             .map_err(Error::Storage)?;
         Ok(())
     }
+
+    pub fn get_target_key(&self) -> Result<Option<[u8; 32]>, Error> {
+        self.storage
+            .read("keys/targets")
+            .map_err(Error::Storage)?
+            .map(|s| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(s.trim())
+                    .ok()
+                    .and_then(|key| key.try_into().ok())
+                    .ok_or(Error::MalformedTargetKey)
+            })
+            .transpose()
+    }
+
+    pub fn create_target_key(&self, key: &[u8; 32]) -> Result<(), Error> {
+        self.storage
+            .write(
+                "keys/targets",
+                &base64::engine::general_purpose::STANDARD.encode(key),
+            )
+            .map_err(Error::Storage)
+    }
 }
@@ -1,4 +1,7 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError, TryLockError,
+};
 
 #[derive(Debug, Clone)]
 pub struct Dir {
@@ -16,12 +19,42 @@ impl Dir {
     ) -> Result<R, std::io::Error> {
         self.inner.batch(action)
     }
+
+    /// Like `batch`, but returns `None` instead of waiting if the directory is locked.
+    pub fn try_batch<R>(
+        &self,
+        action: impl FnOnce(&std::path::Path) -> R,
+    ) -> Result<Option<R>, std::io::Error> {
+        self.inner.try_batch(action)
+    }
+
+    /// Whether a previous batch failed to release the lock. The next batch releases it before
+    /// going ahead.
+    pub fn is_stuck(&self) -> bool {
+        self.inner.stuck.load(Ordering::SeqCst)
+    }
+
+    /// Releases a lock that a previous batch failed to release.
+    pub fn force_unlock(&self) -> Result<(), std::io::Error> {
+        self.inner.recover(&mut self.inner.lock_file())
+    }
+
+    // makes the next release of the lock fail as the system call failing would: with an
+    // error, and with the lock still held.
+    #[cfg(test)]
+    pub(crate) fn fail_next_unlock(&self) {
+        self.inner.fail_next_unlock.store(true, Ordering::SeqCst);
+    }
 }
 
 #[derive(Debug)]
 struct Inner {
     path: std::path::PathBuf,
     flock: Mutex<fslock::LockFile>,
+    // whether releasing the lock failed, so that it may still be held.
+    stuck: AtomicBool,
+    #[cfg(test)]
+    fail_next_unlock: AtomicBool,
 }
 
 impl Inner {
@@ -35,19 +68,85 @@ impl Inner {
                 format!("{} is not a directory", path.display()),
             ));
         }
-        let flock = fslock::LockFile::open(&path.with_extension("lock")).map(Mutex::new)?;
-        Ok(Self { path, flock })
+        let flock = fslock::LockFile::open(&lock_path(&path)).map(Mutex::new)?;
+        Ok(Self {
+            path,
+            flock,
+            stuck: AtomicBool::new(false),
+            #[cfg(test)]
+            fail_next_unlock: AtomicBool::new(false),
+        })
+    }
+
+    fn lock_file(&self) -> std::sync::MutexGuard<'_, fslock::LockFile> {
+        self.flock.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
     fn batch<R>(&self, action: impl FnOnce(&std::path::Path) -> R) -> Result<R, std::io::Error> {
-        let mut flock = self.flock.lock().unwrap();
+        let mut flock = self.lock_file();
 
+        self.recover(&mut flock)?;
         flock.lock()?;
         let result = action(&self.path);
-        flock.unlock()?;
+        self.unlock(&mut flock);
 
         Ok(result)
     }
+
+    fn try_batch<R>(
+        &self,
+        action: impl FnOnce(&std::path::Path) -> R,
+    ) -> Result<Option<R>, std::io::Error> {
+        let mut flock = match self.flock.try_lock() {
+            Ok(flock) => flock,
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        };
+
+        self.recover(&mut flock)?;
+        if !flock.try_lock()? {
+            return Ok(None);
+        }
+        let result = action(&self.path);
+        self.unlock(&mut flock);
+
+        Ok(Some(result))
+    }
+
+    // the action has already run by the time the lock is released, so failing to release it
+    // is logged rather than reported. the lock may then stay held until `recover` releases it.
+    fn unlock(&self, flock: &mut fslock::LockFile) {
+        if let Err(error) = self.release(flock) {
+            tracing::error!(path = %self.path.display(), ?error, "failed to unlock directory");
+            self.stuck.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn release(&self, flock: &mut fslock::LockFile) -> Result<(), std::io::Error> {
+        #[cfg(test)]
+        if self.fail_next_unlock.swap(false, Ordering::SeqCst) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "injected unlock failure",
+            ));
+        }
+        flock.unlock()
+    }
+
+    // a failed unlock can leave the lock held whether or not the lock file says it owns it, so
+    // the file is reopened instead, as closing it releases the lock either way.
+    fn recover(&self, flock: &mut fslock::LockFile) -> Result<(), std::io::Error> {
+        if self.stuck.load(Ordering::SeqCst) {
+            tracing::warn!(path = %self.path.display(), "releasing stuck directory lock");
+            *flock = fslock::LockFile::open(&lock_path(&self.path))?;
+            self.stuck.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+fn lock_path(path: &std::path::Path) -> std::path::PathBuf {
+    path.with_extension("lock")
 }
 
 #[cfg(test)]
@@ -98,6 +197,80 @@ mod tests {
         );
     }
 
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // what's logged while running `action`.
+    fn logged(action: impl FnOnce()) -> String {
+        let logs = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer({
+                let logs = Arc::clone(&logs);
+                move || Logs(Arc::clone(&logs))
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, action);
+        let logs = logs.lock().unwrap();
+        String::from_utf8_lossy(&logs).into_owned()
+    }
+
+    #[test]
+    fn test_unlock_failure() {
+        let dir_path = temp_dir();
+        let dir = Dir::new(&dir_path).unwrap();
+        let other_dir = Dir::new(&dir_path).unwrap();
+
+        dir.fail_next_unlock();
+        let logs = logged(|| {
+            dir.batch(|root| std::fs::write(root.join("file.txt"), "1"))
+                .unwrap()
+                .unwrap();
+        });
+        assert!(logs.contains("failed to unlock directory"));
+        assert!(logs.contains("injected unlock failure"));
+        assert!(dir.is_stuck());
+        // the lock is still held
+        assert!(other_dir.try_batch(|_| ()).unwrap().is_none());
+
+        // the next batch releases the stuck lock first
+        let logs = logged(|| {
+            dir.batch(|root| std::fs::write(root.join("file.txt"), "2"))
+                .unwrap()
+                .unwrap();
+        });
+        assert!(logs.contains("releasing stuck directory lock"));
+        assert!(!dir.is_stuck());
+        assert!(other_dir.try_batch(|_| ()).unwrap().is_some());
+        assert_eq!(
+            std::fs::read_to_string(dir_path.join("file.txt")).unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let dir = Dir::new(temp_dir()).unwrap();
+
+        dir.fail_next_unlock();
+        dir.batch(|_| ()).unwrap();
+        assert!(dir.is_stuck());
+
+        dir.force_unlock().unwrap();
+        assert!(!dir.is_stuck());
+        assert!(dir.try_batch(|_| ()).unwrap().is_some());
+    }
+
     #[tokio::test]
     async fn test_lock_different_instances() {
         let dir_path = temp_dir();
@@ -78,6 +78,14 @@ pub struct Project {
     pub project_data_last_fetch: Option<FetchResult>,
     #[serde(default)]
     pub omit_certificate_check: Option<bool>,
+    /// if true, the default target is also stored in the repository's git config, and read from
+    /// there when the gitbutler data has none
+    #[serde(default)]
+    pub store_targets_in_git_config: Option<bool>,
+    /// if true, the remote urls of targets, which may embed credentials, are encrypted in the
+    /// gitbutler data, see `keys::Controller::get_or_create_target_key`
+    #[serde(default)]
+    pub encrypt_target_urls: Option<bool>,
 }
 
 impl AsRef<Project> for Project {
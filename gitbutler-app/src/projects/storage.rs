@@ -47,6 +47,8 @@ pub struct UpdateRequest {
     pub gitbutler_code_push_state: Option<project::CodePushState>,
     pub project_data_last_fetched: Option<project::FetchResult>,
     pub omit_certificate_check: Option<bool>,
+    pub store_targets_in_git_config: Option<bool>,
+    pub encrypt_target_urls: Option<bool>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -139,6 +141,14 @@ impl Storage {
             project.omit_certificate_check = Some(omit_certificate_check);
         }
 
+        if let Some(store_targets_in_git_config) = update_request.store_targets_in_git_config {
+            project.store_targets_in_git_config = Some(store_targets_in_git_config);
+        }
+
+        if let Some(encrypt_target_urls) = update_request.encrypt_target_urls {
+            project.encrypt_target_urls = Some(encrypt_target_urls);
+        }
+
         self.storage
             .write(PROJECTS_FILE, &serde_json::to_string_pretty(&projects)?)?;
 
@@ -1,4 +1,4 @@
-use std::{num, path, str};
+use std::{io::Read, num, path, str};
 
 use anyhow::{Context, Result};
 use serde::{ser::SerializeStruct, Serialize};
@@ -31,6 +31,8 @@ pub enum Reader<'reader> {
     Filesystem(FilesystemReader),
     Commit(CommitReader<'reader>),
     Prefixed(PrefixedReader<'reader>),
+    #[cfg(test)]
+    Memory(MemoryReader),
 }
 
 impl<'reader> Reader<'reader> {
@@ -38,6 +40,11 @@ impl<'reader> Reader<'reader> {
         FilesystemReader::open(root).map(Reader::Filesystem)
     }
 
+    // reads the already opened directory, see `writer::DirWriter::reader`.
+    pub(crate) fn from_dir(dir: lock::Dir) -> Self {
+        Reader::Filesystem(FilesystemReader(dir))
+    }
+
     pub fn sub<P: AsRef<path::Path>>(&'reader self, prefix: P) -> Self {
         Reader::Prefixed(PrefixedReader::new(self, prefix))
     }
@@ -47,6 +54,8 @@ impl<'reader> Reader<'reader> {
             Reader::Filesystem(_) => None,
             Reader::Commit(reader) => Some(reader.get_commit_oid()),
             Reader::Prefixed(reader) => reader.reader.commit_id(),
+            #[cfg(test)]
+            Reader::Memory(_) => None,
         }
     }
 
@@ -62,6 +71,8 @@ impl<'reader> Reader<'reader> {
             Reader::Filesystem(reader) => reader.exists(file_path),
             Reader::Commit(reader) => Ok(reader.exists(file_path)),
             Reader::Prefixed(reader) => reader.exists(file_path),
+            #[cfg(test)]
+            Reader::Memory(reader) => Ok(reader.exists(file_path)),
         }
     }
 
@@ -72,29 +83,74 @@ impl<'reader> Reader<'reader> {
             .expect("batch should return at least one result")
     }
 
+    /// Reads the file as is, for files that are not text, which `read` doesn't return the
+    /// contents of.
+    pub fn read_bytes<P: AsRef<path::Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        match self {
+            Reader::Filesystem(reader) => reader.batch(|root| {
+                let path = root.join(path);
+                if !path.exists() {
+                    return Err(Error::NotFound);
+                }
+                Ok(std::fs::read(path)?)
+            })?,
+            Reader::Commit(reader) => reader.read_bytes(path.as_ref()),
+            Reader::Prefixed(reader) => reader.read_bytes(path.as_ref()),
+            #[cfg(test)]
+            Reader::Memory(reader) => reader
+                .read(path.as_ref())
+                .map(String::into_bytes)
+                .ok_or(Error::NotFound),
+        }
+    }
+
     pub fn batch<P: AsRef<path::Path>>(
         &self,
         paths: &[P],
     ) -> Result<Vec<Result<Content, Error>>, std::io::Error> {
+        self.batch_limited(paths, &|_| None)
+    }
+
+    /// Like `batch`, but files are returned as `Content::Large` when they are larger than the
+    /// size `max_size` returns for their path, without reading more than that of them. Paths
+    /// it returns no size for are limited as in `batch`.
+    pub fn batch_limited<P: AsRef<path::Path>>(
+        &self,
+        paths: &[P],
+        max_size: &dyn Fn(&path::Path) -> Option<usize>,
+    ) -> Result<Vec<Result<Content, Error>>, std::io::Error> {
+        let max_size = |path: &path::Path| max_size(path).unwrap_or(Content::MAX_SIZE);
         match self {
             Reader::Filesystem(reader) => reader.batch(|root| {
                 paths
                     .iter()
                     .map(|path| {
+                        let max_size = max_size(path.as_ref());
                         let path = root.join(path);
                         if !path.exists() {
                             return Err(Error::NotFound);
                         }
-                        let content = Content::try_from(&path)?;
+                        let content = Content::read_limited(&path, max_size)?;
                         Ok(content)
                     })
                     .collect()
             }),
             Reader::Commit(reader) => Ok(paths
                 .iter()
-                .map(|path| reader.read(path.as_ref()))
+                .map(|path| reader.read_limited(path.as_ref(), max_size(path.as_ref())))
+                .collect()),
+            Reader::Prefixed(reader) => reader.batch_limited(paths, &|path| Some(max_size(path))),
+            #[cfg(test)]
+            Reader::Memory(reader) => Ok(paths
+                .iter()
+                .map(|path| match reader.read(path.as_ref()) {
+                    Some(contents) if contents.len() > max_size(path.as_ref()) => {
+                        Ok(Content::Large)
+                    }
+                    Some(contents) => Ok(Content::UTF8(contents)),
+                    None => Err(Error::NotFound),
+                })
                 .collect()),
-            Reader::Prefixed(reader) => reader.batch(paths),
         }
     }
 
@@ -103,6 +159,8 @@ impl<'reader> Reader<'reader> {
             Reader::Filesystem(reader) => reader.list_files(dir_path.as_ref()),
             Reader::Commit(reader) => reader.list_files(dir_path.as_ref()),
             Reader::Prefixed(reader) => reader.list_files(dir_path.as_ref()),
+            #[cfg(test)]
+            Reader::Memory(reader) => Ok(reader.list_files(dir_path.as_ref())),
         }
     }
 }
@@ -131,6 +189,40 @@ impl FilesystemReader {
     }
 }
 
+/// Reads the files kept by a `writer::InMemoryWriter`, seeing what it writes after the reader
+/// was created too.
+#[cfg(test)]
+pub struct MemoryReader(
+    pub(crate) std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<path::PathBuf, String>>>,
+);
+
+#[cfg(test)]
+impl MemoryReader {
+    fn read(&self, path: &path::Path) -> Option<String> {
+        self.0.lock().unwrap().get(path).cloned()
+    }
+
+    // directories exist as long as a file in them does, as they do in a git tree.
+    fn exists<P: AsRef<path::Path>>(&self, path: P) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|file_path| file_path.starts_with(path.as_ref()))
+    }
+
+    fn list_files(&self, dir_path: &path::Path) -> Vec<path::PathBuf> {
+        self.0
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|file_path| file_path.strip_prefix(dir_path).ok())
+            .filter(|file_path| !file_path.as_os_str().is_empty())
+            .map(path::Path::to_path_buf)
+            .collect()
+    }
+}
+
 pub struct CommitReader<'reader> {
     repository: &'reader git::Repository,
     commit_oid: git::Oid,
@@ -156,7 +248,11 @@ impl<'reader> CommitReader<'reader> {
         self.commit_oid
     }
 
-    fn read<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Content, Error> {
+    fn read_limited<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        max_size: usize,
+    ) -> Result<Content, Error> {
         let path = path.as_ref();
         let entry = match self
             .tree
@@ -170,9 +266,24 @@ impl<'reader> CommitReader<'reader> {
             Ok(blob) => blob,
             Err(_) => return Err(Error::NotFound),
         };
+        if blob.size() > max_size {
+            return Ok(Content::Large);
+        }
         Ok(Content::from(&blob))
     }
 
+    fn read_bytes<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        let entry = self
+            .tree
+            .get_path(path.as_ref())
+            .map_err(|_| Error::NotFound)?;
+        let blob = self
+            .repository
+            .find_blob(entry.id())
+            .map_err(|_| Error::NotFound)?;
+        Ok(blob.content().to_vec())
+    }
+
     fn list_files<P: AsRef<std::path::Path>>(&self, dir_path: P) -> Result<Vec<path::PathBuf>> {
         let dir_path = dir_path.as_ref();
         let mut files = vec![];
@@ -221,12 +332,24 @@ impl<'r> PrefixedReader<'r> {
     pub fn batch<P: AsRef<path::Path>>(
         &self,
         paths: &[P],
+    ) -> Result<Vec<Result<Content, Error>>, std::io::Error> {
+        self.batch_limited(paths, &|_| None)
+    }
+
+    fn batch_limited<P: AsRef<path::Path>>(
+        &self,
+        paths: &[P],
+        max_size: &dyn Fn(&path::Path) -> Option<usize>,
     ) -> Result<Vec<Result<Content, Error>>, std::io::Error> {
         let paths = paths
             .iter()
             .map(|path| self.prefix.join(path))
             .collect::<Vec<_>>();
-        self.reader.batch(paths.as_slice())
+        self.reader.batch_limited(paths.as_slice(), max_size)
+    }
+
+    fn read_bytes<P: AsRef<path::Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        self.reader.read_bytes(self.prefix.join(path))
     }
 
     fn list_files<P: AsRef<std::path::Path>>(&self, dir_path: P) -> Result<Vec<path::PathBuf>> {
@@ -285,6 +408,20 @@ impl Serialize for Content {
 
 impl Content {
     const MAX_SIZE: usize = 1024 * 1024 * 10; // 10 MB
+
+    // reads no more of the file than needed to tell that it's larger than max_size, so that
+    // neither a huge file nor one that grows while it's read is read whole.
+    fn read_limited(path: &path::Path, max_size: usize) -> Result<Self, std::io::Error> {
+        let limit = u64::try_from(max_size).map_or(u64::MAX, |size| size.saturating_add(1));
+        let mut bytes = vec![];
+        std::fs::File::open(path)?
+            .take(limit)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() > max_size {
+            return Ok(Content::Large);
+        }
+        Ok(bytes.as_slice().into())
+    }
 }
 
 impl From<&str> for Content {
@@ -462,6 +599,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_directory_reader_batch_limited() -> Result<()> {
+        let dir = test_utils::temp_dir();
+        std::fs::write(dir.join("small.txt"), "small")?;
+        std::fs::write(dir.join("large.txt"), "large")?;
+        // sparse, so that it takes no space, but would take a while to read whole
+        std::fs::File::create(dir.join("huge.txt"))?.set_len(1 << 32)?;
+
+        let reader = Reader::open(dir.clone())?;
+        let results = reader.batch_limited(&["small.txt", "large.txt", "huge.txt"], &|path| {
+            (path != path::Path::new("small.txt")).then_some(4)
+        })?;
+        assert_eq!(
+            results.into_iter().collect::<Result<Vec<_>, _>>()?,
+            vec![
+                Content::UTF8("small".to_string()),
+                Content::Large,
+                Content::Large
+            ]
+        );
+
+        // prefixed readers are limited by the full path
+        std::fs::create_dir(dir.join("sub"))?;
+        std::fs::write(dir.join("sub/large.txt"), "large")?;
+        let results = reader.sub("sub").batch_limited(&["large.txt"], &|path| {
+            (path == path::Path::new("sub/large.txt")).then_some(4)
+        })?;
+        assert!(matches!(results.as_slice(), [Ok(Content::Large)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_reader_read_bytes() -> Result<()> {
+        let dir = test_utils::temp_dir();
+
+        let file_path = path::Path::new("test.bin");
+        std::fs::write(dir.join(file_path), [0_u8, 159, 146, 150])?;
+
+        let reader = Reader::open(dir.clone())?;
+        assert_eq!(reader.read(file_path)?, Content::Binary);
+        assert_eq!(reader.read_bytes(file_path)?, vec![0_u8, 159, 146, 150]);
+        assert!(matches!(
+            reader.read_bytes("missing.bin"),
+            Err(Error::NotFound)
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_commit_reader_read_file() -> Result<()> {
         let repository = test_utils::test_repository();
@@ -2,7 +2,7 @@ use std::{collections::HashMap, path};
 
 use anyhow::{anyhow, Context, Result};
 
-use crate::{gb_repository, reader};
+use crate::{gb_repository, reader, virtual_branches::target};
 
 use super::Session;
 
@@ -11,6 +11,7 @@ pub struct SessionReader<'reader> {
     reader: reader::Reader<'reader>,
     // reader for the previous session's commit
     previous_reader: reader::Reader<'reader>,
+    target_encryption_key: Option<target::EncryptionKey>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -26,6 +27,12 @@ impl<'reader> SessionReader<'reader> {
         &self.reader
     }
 
+    /// The key the urls of targets in the session are encrypted with, if the repository's
+    /// project encrypts them.
+    pub fn target_encryption_key(&self) -> Option<&target::EncryptionKey> {
+        self.target_encryption_key.as_ref()
+    }
+
     pub fn open(repository: &'reader gb_repository::Repository, session: &Session) -> Result<Self> {
         let wd_reader = reader::Reader::open(&repository.root())?;
 
@@ -38,6 +45,7 @@ impl<'reader> SessionReader<'reader> {
                         repository.git_repository(),
                         &head_commit,
                     )?,
+                    target_encryption_key: repository.target_encryption_key().cloned(),
                 });
             }
         }
@@ -63,6 +71,7 @@ impl<'reader> SessionReader<'reader> {
                 repository.git_repository(),
                 &commit.parent(0)?,
             )?,
+            target_encryption_key: repository.target_encryption_key().cloned(),
         })
     }
 
@@ -1,8 +1,15 @@
-use std::{collections::HashMap, fs, path};
+use std::{
+    collections::HashMap,
+    fs, path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use tempfile::tempdir;
 
-use crate::{database, gb_repository, git, keys, project_repository, projects, storage, users};
+use crate::{
+    database, gb_repository, git, keys, project_repository, projects, storage, users,
+    virtual_branches::{branch, target, BranchId},
+};
 
 pub struct Suite {
     pub local_app_data: path::PathBuf,
@@ -127,6 +134,54 @@ impl<'a> Case<'a> {
     }
 }
 
+/// A target tracking `origin/master`, which tests adjust with struct update syntax, e.g.
+/// `Target { behind: 1, ..test_target() }`, so that adding a field to `Target` only takes
+/// changing this.
+pub fn test_target() -> target::Target {
+    target::Target {
+        branch: "refs/remotes/origin/master".parse().unwrap(),
+        remote_url: "remote url".to_string(),
+        sha: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
+    }
+}
+
+static TEST_BRANCH_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// A virtual branch with a new id, and names, timestamps and shas that differ from those of
+/// the branches created before it.
+pub fn test_branch() -> branch::Branch {
+    let index = TEST_BRANCH_INDEX.fetch_add(1, Ordering::Relaxed) + 1;
+    branch::Branch {
+        id: BranchId::generate(),
+        name: format!("branch_name_{}", index),
+        notes: String::new(),
+        applied: true,
+        upstream: Some(
+            format!("refs/remotes/origin/upstream_{}", index)
+                .parse()
+                .unwrap(),
+        ),
+        upstream_head: None,
+        created_timestamp_ms: index as u128,
+        updated_timestamp_ms: (index + 100) as u128,
+        head: format!("{:040x}", index).parse().unwrap(),
+        tree: format!("{:040x}", index + 10).parse().unwrap(),
+        ownership: branch::Ownership::default(),
+        order: index,
+        selected_for_changes: None,
+    }
+}
+
 pub fn test_database() -> database::Database {
     database::Database::try_from(&temp_dir()).unwrap()
 }
@@ -33,3 +33,6 @@ pub use r#virtual::*;
 
 mod remote;
 pub use remote::*;
+
+mod snapshot;
+pub use snapshot::*;
@@ -67,20 +67,6 @@ pub fn set_base_branch(
         Err(error) => Err(errors::SetBaseBranchError::Other(error.into())),
     }?;
 
-    let remote = repo
-        .find_remote(target_branch_ref.remote())
-        .context(format!(
-            "failed to find remote for branch {}",
-            target_branch.name().unwrap()
-        ))?;
-    let remote_url = remote
-        .url()
-        .context(format!(
-            "failed to get remote url for {}",
-            target_branch_ref.remote()
-        ))?
-        .unwrap();
-
     let target_branch_head = target_branch.peel_to_commit().context(format!(
         "failed to peel branch {} to commit",
         target_branch.name().unwrap()
@@ -116,14 +102,21 @@ pub fn set_base_branch(
     };
 
     let target = target::Target {
-        branch: target_branch_ref.clone(),
-        remote_url: remote_url.to_string(),
         sha: commit_oid,
+        ..target::Target::from_upstream(
+            repo,
+            target_branch_ref.remote(),
+            target_branch_ref.branch(),
+        )
+        .context("failed to create target")?
     };
 
     let target_writer =
         target::Writer::new(gb_repository).context("failed to create target writer")?;
-    target_writer.write_default(&target)?;
+    target_writer
+        .write_default(&target)
+        .context("failed to write default target")?;
+    store_default_target_in_git_config(gb_repository, project_repository)?;
 
     let head_name: git::Refname = current_head
         .name()
@@ -518,24 +511,62 @@ pub fn update_base_branch(
     // write new target oid
     let target_writer =
         target::Writer::new(gb_repository).context("failed to create target writer")?;
-    target_writer.write_default(&target::Target {
-        sha: new_target_commit.id(),
-        ..target
-    })?;
+    target_writer
+        .write_default(&target::Target {
+            sha: new_target_commit.id(),
+            ..target
+        })
+        .context("failed to write default target")?;
+    store_default_target_in_git_config(gb_repository, project_repository)?;
 
     super::integration::update_gitbutler_integration(gb_repository, project_repository)?;
 
     Ok(())
 }
 
+/// Records where the default target branch is after fetching its remote, returning how many
+/// commits the target is behind it. Nothing is written, and no session created, if the fetch
+/// didn't move the branch.
+pub fn record_target_fetch(
+    gb_repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    target: &target::Target,
+) -> Result<usize> {
+    let repo = &project_repository.git_repository;
+    let tip = target.tip(repo)?;
+    if target.last_fetched_sha == Some(tip) {
+        return Ok(target.behind);
+    }
+    let behind = target::Writer::new(gb_repository)
+        .context("failed to open target writer")?
+        .record_default_fetch(repo, tip)?;
+    store_default_target_in_git_config(gb_repository, project_repository)?;
+    Ok(behind)
+}
+
+// copies the default target just written to the repository's git config, if the project is set
+// to store targets there, so that it travels with the repository.
+fn store_default_target_in_git_config(
+    gb_repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+) -> Result<()> {
+    let Some(config_writer) = target::GitConfigTargetWriter::for_project(project_repository) else {
+        return Ok(());
+    };
+    if let Some(target) = gb_repository.default_target()? {
+        config_writer
+            .write_default(&target)
+            .context("failed to write default target to git config")?;
+    }
+    Ok(())
+}
+
 pub fn target_to_base_branch(
     project_repository: &project_repository::Repository,
     target: &target::Target,
 ) -> Result<super::BaseBranch> {
     let repo = &project_repository.git_repository;
-    let branch = repo.find_branch(&target.branch.clone().into())?;
-    let commit = branch.peel_to_commit()?;
-    let oid = commit.id();
+    let oid = target.tip(repo)?;
 
     // gather a list of commits between oid and target.sha
     let upstream_commits = project_repository
@@ -1,14 +1,16 @@
 mod file_ownership;
+mod fsck;
 mod hunk;
 mod ownership;
 mod reader;
 mod writer;
 
 pub use file_ownership::FileOwnership;
+pub use fsck::{fsck, FsckReport};
 pub use hunk::Hunk;
-pub use ownership::Ownership;
+pub use ownership::{Conflict as OwnershipConflict, FileOwnershipDiff, Ownership, OwnershipDiff};
 pub use reader::BranchReader as Reader;
-pub use writer::BranchWriter as Writer;
+pub use writer::{branch_disk_usage, BranchWriter as Writer, DiskUsage};
 
 use serde::{Deserialize, Serialize};
 
@@ -51,6 +53,12 @@ impl Branch {
     }
 }
 
+impl From<&Branch> for BranchId {
+    fn from(branch: &Branch) -> Self {
+        branch.id
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct BranchUpdateRequest {
     pub id: BranchId,
@@ -160,13 +168,31 @@ impl TryFrom<&crate::reader::Reader<'_>> for Branch {
         let created_timestamp_ms = results[9].clone()?.try_into()?;
         let updated_timestamp_ms = results[10].clone()?.try_into()?;
 
-        let ownership_string: String = results[11].clone()?.try_into()?;
-        let ownership = ownership_string.parse().map_err(|e| {
-            crate::reader::Error::Io(
-                std::io::Error::new(std::io::ErrorKind::Other, format!("meta/ownership: {}", e))
+        // the binary ownership takes precedence over the text one
+        let ownership = match reader.read_bytes("ownership.bin") {
+            Ok(bytes) => Ownership::from_bytes(&bytes).map_err(|e| {
+                crate::reader::Error::Io(
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("ownership.bin: {}", e),
+                    )
                     .into(),
-            )
-        })?;
+                )
+            })?,
+            Err(crate::reader::Error::NotFound) => {
+                let ownership_string: String = results[11].clone()?.try_into()?;
+                ownership_string.parse().map_err(|e| {
+                    crate::reader::Error::Io(
+                        std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("meta/ownership: {}", e),
+                        )
+                        .into(),
+                    )
+                })?
+            }
+            Err(e) => return Err(e),
+        };
 
         let selected_for_changes = match results[12].clone() {
             Ok(raw_ts) => {
@@ -0,0 +1,227 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git, reader, writer};
+
+use super::BranchId;
+
+// files of a branch that hold an oid, if they exist.
+const OID_FILES: [&str; 4] = ["meta/head", "meta/tree", "meta/upstream_head", "target/sha"];
+
+/// Defects `fsck` found in the `branches/` directory, by category.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Branches that have a target, but no meta, as the branch itself is gone.
+    pub orphaned_targets: Vec<BranchId>,
+    /// Branches without a `meta/name`.
+    pub missing_names: Vec<BranchId>,
+    /// Branches with a target in the one file per field layout, but no `target/sha`.
+    pub missing_target_shas: Vec<BranchId>,
+    /// Files that should hold an oid but don't, relative to the gitbutler data directory.
+    pub malformed_oids: Vec<PathBuf>,
+    /// Orphaned targets that were removed, when repairing.
+    pub repaired: Vec<BranchId>,
+}
+
+impl FsckReport {
+    /// Whether no defects were found, or all of them were repaired.
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_targets
+            .iter()
+            .all(|id| self.repaired.contains(id))
+            && self.missing_names.is_empty()
+            && self.missing_target_shas.is_empty()
+            && self.malformed_oids.is_empty()
+    }
+}
+
+/// Checks the `branches/` directory for defects that accumulate over time, such as targets
+/// left behind by deleted branches. With `repair`, orphaned targets are removed; other
+/// defects are only reported, as repairing them would lose data.
+pub fn fsck(repository: &gb_repository::Repository, repair: bool) -> Result<FsckReport> {
+    let reader = reader::Reader::open(repository.root())?;
+
+    let mut files_by_branch = BTreeMap::<BranchId, Vec<PathBuf>>::new();
+    for path in reader.list_files("branches")? {
+        let mut components = path.components();
+        let Some(id) = components
+            .next()
+            .and_then(|id| id.as_os_str().to_str()?.parse::<BranchId>().ok())
+        else {
+            // the default target, remotes and the like
+            continue;
+        };
+        files_by_branch
+            .entry(id)
+            .or_default()
+            .push(components.as_path().to_path_buf());
+    }
+
+    let mut report = FsckReport::default();
+    for (id, files) in &files_by_branch {
+        let has = |path: &str| files.iter().any(|file| file.starts_with(path));
+
+        if !has("meta") {
+            if has("target") || has("target.json") {
+                report.orphaned_targets.push(*id);
+            }
+            continue;
+        }
+
+        if !has("meta/name") {
+            report.missing_names.push(*id);
+        }
+        if has("target") && !has("target/sha") {
+            report.missing_target_shas.push(*id);
+        }
+
+        let dir = Path::new("branches").join(id.to_string());
+        for oid_file in OID_FILES.into_iter().filter(|file| has(file)) {
+            let path = dir.join(oid_file);
+            let contents: String = reader
+                .read(&path)?
+                .try_into()
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if contents.parse::<git::Oid>().is_err() {
+                report.malformed_oids.push(path);
+            }
+        }
+    }
+
+    if repair && !report.orphaned_targets.is_empty() {
+        repository
+            .mark_active_session()
+            .context("failed to get or create current session")?;
+        let writer = writer::DirWriter::open(repository.root())?;
+        for id in &report.orphaned_targets {
+            writer
+                .remove(format!("branches/{}", id))
+                .with_context(|| format!("failed to remove orphaned target of {}", id))?;
+            report.repaired.push(*id);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_utils::{test_target, Case, Suite},
+        virtual_branches::{branch, target},
+    };
+
+    use super::*;
+
+    fn test_branch() -> branch::Branch {
+        branch::Branch {
+            id: BranchId::generate(),
+            name: "branch".to_string(),
+            notes: String::new(),
+            applied: true,
+            upstream: None,
+            upstream_head: None,
+            created_timestamp_ms: 0,
+            updated_timestamp_ms: 0,
+            head: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            tree: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            ownership: branch::Ownership::default(),
+            order: 0,
+            selected_for_changes: None,
+        }
+    }
+
+    #[test]
+    fn test_fsck_clean() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+        let target_writer = target::Writer::new(&gb_repository)?;
+        target_writer.write_default(&test_target())?;
+        target_writer.write(&branch.id, &test_target())?;
+
+        let report = fsck(&gb_repository, false)?;
+        assert_eq!(report, FsckReport::default());
+        assert!(report.is_clean());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let branch_writer = branch::Writer::new(&gb_repository)?;
+        let target_writer = target::Writer::new(&gb_repository)?;
+        let writer = writer::DirWriter::open(gb_repository.root())?;
+
+        // the branch is gone, but its target is not
+        let orphan = test_branch();
+        target_writer.write(&orphan.id, &test_target())?;
+
+        let mut unnamed = test_branch();
+        branch_writer.write(&mut unnamed)?;
+        writer.remove(format!("branches/{}/meta/name", unnamed.id))?;
+
+        let mut shaless = test_branch();
+        branch_writer.write(&mut shaless)?;
+        target_writer.write(&shaless.id, &test_target())?;
+        writer.remove(format!("branches/{}/target/sha", shaless.id))?;
+
+        let mut malformed = test_branch();
+        branch_writer.write(&mut malformed)?;
+        writer.write_string(
+            &format!("branches/{}/meta/head", malformed.id),
+            "not an oid",
+        )?;
+
+        let report = fsck(&gb_repository, false)?;
+        assert_eq!(
+            report,
+            FsckReport {
+                orphaned_targets: vec![orphan.id],
+                missing_names: vec![unnamed.id],
+                missing_target_shas: vec![shaless.id],
+                malformed_oids: vec![Path::new("branches")
+                    .join(malformed.id.to_string())
+                    .join("meta/head")],
+                repaired: vec![],
+            }
+        );
+        assert!(!report.is_clean());
+        assert!(gb_repository
+            .root()
+            .join("branches")
+            .join(orphan.id.to_string())
+            .exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_repair() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let orphan = test_branch();
+        target::Writer::new(&gb_repository)?.write(&orphan.id, &test_target())?;
+
+        let report = fsck(&gb_repository, true)?;
+        assert_eq!(report.orphaned_targets, vec![orphan.id]);
+        assert_eq!(report.repaired, vec![orphan.id]);
+        assert!(report.is_clean());
+        assert!(!gb_repository
+            .root()
+            .join("branches")
+            .join(orphan.id.to_string())
+            .exists());
+
+        assert_eq!(fsck(&gb_repository, false)?, FsckReport::default());
+
+        Ok(())
+    }
+}
@@ -109,7 +109,11 @@ impl Hunk {
         timestamp_ms: Option<u128>,
     ) -> Result<Self> {
         if start > end {
-            Err(anyhow!("invalid range: {}-{}", start, end))
+            Err(anyhow!(
+                "invalid range: {}-{}, end is before start",
+                start,
+                end
+            ))
         } else {
             Ok(Hunk {
                 start,
@@ -146,6 +150,32 @@ impl Hunk {
         self.start <= *line && self.end >= *line
     }
 
+    // returns what is left of this hunk after removing the lines of another: nothing, one
+    // trimmed hunk, or two hunks if another falls strictly inside this one.
+    pub fn subtract(&self, another: &Hunk) -> Vec<Hunk> {
+        if !self.intersects(another) {
+            return vec![self.clone()];
+        }
+        let mut left = vec![];
+        if self.start < another.start {
+            left.push(Hunk {
+                start: self.start,
+                end: another.start - 1,
+                hash: None,
+                timestamp_ms: self.timestamp_ms,
+            });
+        }
+        if another.end < self.end {
+            left.push(Hunk {
+                start: another.end + 1,
+                end: self.end,
+                hash: None,
+                timestamp_ms: self.timestamp_ms,
+            });
+        }
+        left
+    }
+
     pub fn intersects(&self, another: &Hunk) -> bool {
         self.contains(&another.start)
             || self.contains(&another.end)
@@ -190,6 +220,35 @@ mod tests {
         "3-2".parse::<Hunk>().unwrap_err();
     }
 
+    #[test]
+    fn parse_inverted_error() {
+        let error = "3-2".parse::<Hunk>().unwrap_err();
+        assert_eq!(error.to_string(), "invalid range: 3-2, end is before start");
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for (raw, expected) in [
+            ("", "failed to parse start of range: "),
+            ("1", "invalid range: 1"),
+            ("a-2", "failed to parse start of range: a-2"),
+            ("1-b", "failed to parse end of range: 1-b"),
+            (
+                "1-2-hash-ts",
+                "failed to parse timestamp_ms of range: 1-2-hash-ts",
+            ),
+        ] {
+            let error = raw.parse::<Hunk>().unwrap_err();
+            assert_eq!(error.to_string(), expected, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn parse_single_line() {
+        // deletions produce hunks that span no new lines
+        assert_eq!("2-2".parse::<Hunk>().unwrap(), Hunk::from(2..=2));
+    }
+
     #[test]
     fn to_string_no_hash() {
         assert_eq!(
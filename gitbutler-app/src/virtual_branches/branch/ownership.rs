@@ -1,14 +1,64 @@
-use std::{fmt, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Read},
+    path,
+    str::FromStr,
+};
 
+use anyhow::{anyhow, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize, Serializer};
 
-use super::FileOwnership;
+use super::{FileOwnership, Hunk};
+
+// the binary encoding starts with these, followed by the version of the format.
+const BINARY_MAGIC: &[u8; 4] = b"GBOW";
+const BINARY_VERSION: u8 = 1;
+// flags of a hunk in the binary encoding, telling which of its optional fields follow.
+const HUNK_HAS_HASH: u8 = 1;
+const HUNK_HAS_TIMESTAMP: u8 = 2;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Ownership {
     pub files: Vec<FileOwnership>,
 }
 
+/// Hunks claimed by both sides of an [`Ownership::merge`] that overlap without being equal.
+#[derive(Debug, thiserror::Error)]
+#[error("{} overlapping hunk(s)", overlaps.len())]
+pub struct Conflict {
+    pub overlaps: Vec<(path::PathBuf, Hunk, Hunk)>,
+}
+
+/// The difference between two ownerships, as returned by [`Ownership::diff`]. Files are listed
+/// in the order of their paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnershipDiff {
+    /// Files only the other ownership owns, with their hunks.
+    pub added: Vec<FileOwnership>,
+    /// Files only this ownership owns, with their hunks.
+    pub removed: Vec<FileOwnership>,
+    /// Files both own, but with different hunks.
+    pub modified: Vec<FileOwnershipDiff>,
+}
+
+/// How the hunks of a file differ between two ownerships.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOwnershipDiff {
+    pub file_path: path::PathBuf,
+    /// Hunks whose range only the other ownership owns.
+    pub added_hunks: Vec<Hunk>,
+    /// Hunks whose range only this ownership owns.
+    pub removed_hunks: Vec<Hunk>,
+}
+
+impl OwnershipDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
 impl Serialize for Ownership {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_str(self.to_string().as_str())
@@ -47,6 +97,108 @@ impl FromStr for Ownership {
 }
 
 impl Ownership {
+    /// Encodes the ownership as a compact binary blob, which is much smaller than the text
+    /// format for branches that own thousands of files. Decoded by `from_bytes`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with_progress(|_| {})
+    }
+
+    /// Like `to_bytes`, calling `progress` with the number of files encoded so far after
+    /// each file.
+    pub fn to_bytes_with_progress(&self, mut progress: impl FnMut(usize)) -> Result<Vec<u8>> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.push(BINARY_VERSION);
+        write_len(&mut bytes, self.files.len())?;
+        for (index, file) in self.files.iter().enumerate() {
+            let file_path = file
+                .file_path
+                .to_str()
+                .with_context(|| format!("{}: path is not utf-8", file.file_path.display()))?;
+            write_str(&mut bytes, file_path)?;
+            write_len(&mut bytes, file.hunks.len())?;
+            for hunk in &file.hunks {
+                bytes.write_u32::<LittleEndian>(hunk.start)?;
+                bytes.write_u32::<LittleEndian>(hunk.end)?;
+                let mut flags = 0_u8;
+                if hunk.hash.is_some() {
+                    flags |= HUNK_HAS_HASH;
+                }
+                if hunk.timestamp_ms.is_some() {
+                    flags |= HUNK_HAS_TIMESTAMP;
+                }
+                bytes.push(flags);
+                if let Some(hash) = &hunk.hash {
+                    write_str(&mut bytes, hash)?;
+                }
+                if let Some(timestamp_ms) = hunk.timestamp_ms {
+                    bytes.write_u128::<LittleEndian>(timestamp_ms)?;
+                }
+            }
+            progress(index + 1);
+        }
+        Ok(bytes)
+    }
+
+    /// Formats the ownership as its `Display` implementation does, calling `progress` with
+    /// the number of files formatted so far after each file.
+    pub fn to_string_with_progress(&self, mut progress: impl FnMut(usize)) -> String {
+        let mut text = String::new();
+        for (index, file) in self.files.iter().enumerate() {
+            text.push_str(&file.to_string());
+            text.push('\n');
+            progress(index + 1);
+        }
+        text
+    }
+
+    /// Decodes an ownership encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut cursor = io::Cursor::new(bytes);
+        let mut magic = [0_u8; 4];
+        cursor
+            .read_exact(&mut magic)
+            .context("failed to read header")?;
+        if &magic != BINARY_MAGIC {
+            return Err(anyhow!("not a binary ownership"));
+        }
+        let version = cursor.read_u8().context("failed to read version")?;
+        if version != BINARY_VERSION {
+            return Err(anyhow!("unsupported binary ownership version {}", version));
+        }
+
+        let mut ownership = Ownership::default();
+        for _ in 0..read_len(&mut cursor)? {
+            let file_path = read_str(&mut cursor).context("failed to read file path")?;
+            let mut hunks = vec![];
+            for _ in 0..read_len(&mut cursor)? {
+                let start = cursor.read_u32::<LittleEndian>()?;
+                let end = cursor.read_u32::<LittleEndian>()?;
+                let flags = cursor.read_u8()?;
+                let hash = if flags & HUNK_HAS_HASH == 0 {
+                    None
+                } else {
+                    Some(read_str(&mut cursor).context("failed to read hunk hash")?)
+                };
+                let timestamp_ms = if flags & HUNK_HAS_TIMESTAMP == 0 {
+                    None
+                } else {
+                    Some(cursor.read_u128::<LittleEndian>()?)
+                };
+                hunks.push(Hunk {
+                    hash,
+                    timestamp_ms,
+                    start,
+                    end,
+                });
+            }
+            ownership.files.push(FileOwnership {
+                file_path: file_path.into(),
+                hunks,
+            });
+        }
+        Ok(ownership)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.files.is_empty()
     }
@@ -96,6 +248,110 @@ impl Ownership {
         }
     }
 
+    // merges another ownership into this one, unioning the hunks of files present in both.
+    // if any hunks overlap without being equal, nothing is merged and the overlaps are returned.
+    pub fn merge(&mut self, other: &Ownership) -> Result<(), Conflict> {
+        let mut overlaps = vec![];
+        for other_file in &other.files {
+            for file in self
+                .files
+                .iter()
+                .filter(|file| file.file_path == other_file.file_path)
+            {
+                for hunk in &file.hunks {
+                    for other_hunk in &other_file.hunks {
+                        if hunk.intersects(other_hunk) && hunk != other_hunk {
+                            overlaps.push((
+                                file.file_path.clone(),
+                                hunk.clone(),
+                                other_hunk.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        if !overlaps.is_empty() {
+            return Err(Conflict { overlaps });
+        }
+
+        for other_file in &other.files {
+            if let Some(file) = self
+                .files
+                .iter_mut()
+                .find(|file| file.file_path == other_file.file_path)
+            {
+                *file = file.plus(other_file);
+            } else {
+                self.files.push(other_file.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    // removes the lines covered by hunk from the file's ownership. owned hunks are trimmed
+    // when the hunk covers one of their edges, split in two when it falls in their middle and
+    // dropped when fully covered. files left with no hunks are dropped as well.
+    pub fn subtract(&mut self, file: &str, hunk: &Hunk) {
+        let file_path = path::Path::new(file);
+        self.files = self
+            .files
+            .iter()
+            .filter_map(|file_ownership| {
+                if file_ownership.file_path != file_path || file_ownership.is_full() {
+                    return Some(file_ownership.clone());
+                }
+                let hunks = file_ownership
+                    .hunks
+                    .iter()
+                    .flat_map(|owned| owned.subtract(hunk))
+                    .collect::<Vec<_>>();
+                (!hunks.is_empty()).then(|| FileOwnership {
+                    file_path: file_ownership.file_path.clone(),
+                    hunks,
+                })
+            })
+            .collect();
+    }
+
+    // what changed going from this ownership to other. hunks are compared by their ranges
+    // only, so a hunk whose hash or timestamp changed is not a change. files listed more than
+    // once are diffed with all of their hunks, and fully owned files as files without hunks.
+    pub fn diff(&self, other: &Ownership) -> OwnershipDiff {
+        let ours = hunks_by_file(self);
+        let theirs = hunks_by_file(other);
+
+        let mut diff = OwnershipDiff::default();
+        for (file_path, hunks) in &ours {
+            let Some(other_hunks) = theirs.get(file_path) else {
+                diff.removed.push(FileOwnership {
+                    file_path: file_path.to_path_buf(),
+                    hunks: hunks.clone(),
+                });
+                continue;
+            };
+            let added_hunks = without_ranges_of(other_hunks, hunks);
+            let removed_hunks = without_ranges_of(hunks, other_hunks);
+            if !added_hunks.is_empty() || !removed_hunks.is_empty() {
+                diff.modified.push(FileOwnershipDiff {
+                    file_path: file_path.to_path_buf(),
+                    added_hunks,
+                    removed_hunks,
+                });
+            }
+        }
+        for (file_path, hunks) in theirs {
+            if !ours.contains_key(file_path) {
+                diff.added.push(FileOwnership {
+                    file_path: file_path.to_path_buf(),
+                    hunks,
+                });
+            }
+        }
+        diff
+    }
+
     // modifies the ownership in-place and returns the file ownership that was taken, if any.
     pub fn take(&mut self, ownership: &FileOwnership) -> Vec<FileOwnership> {
         let mut taken = Vec::new();
@@ -120,6 +376,68 @@ impl Ownership {
     }
 }
 
+fn hunks_by_file(ownership: &Ownership) -> BTreeMap<&path::Path, Vec<Hunk>> {
+    let mut files = BTreeMap::<_, Vec<_>>::new();
+    for file in &ownership.files {
+        files
+            .entry(file.file_path.as_path())
+            .or_default()
+            .extend(file.hunks.iter().cloned());
+    }
+    files
+}
+
+// the hunks whose range is not among those of other.
+fn without_ranges_of(hunks: &[Hunk], other: &[Hunk]) -> Vec<Hunk> {
+    hunks
+        .iter()
+        .filter(|hunk| {
+            !other
+                .iter()
+                .any(|other| other.start == hunk.start && other.end == hunk.end)
+        })
+        .cloned()
+        .collect()
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) -> Result<()> {
+    let len = u32::try_from(len).context("too many entries")?;
+    bytes.write_u32::<LittleEndian>(len)?;
+    Ok(())
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) -> Result<()> {
+    write_len(bytes, value.len())?;
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn read_len(cursor: &mut io::Cursor<&[u8]>) -> Result<usize> {
+    let len = cursor
+        .read_u32::<LittleEndian>()
+        .context("failed to read length")?;
+    Ok(usize::try_from(len)?)
+}
+
+fn read_str(cursor: &mut io::Cursor<&[u8]>) -> Result<String> {
+    let len = read_len(cursor)?;
+    // checked up front, so that a corrupt length doesn't allocate more than there is
+    let remaining = cursor
+        .get_ref()
+        .len()
+        .saturating_sub(usize::try_from(cursor.position())?);
+    if len > remaining {
+        return Err(anyhow!(
+            "length {} exceeds the remaining {} bytes",
+            len,
+            remaining
+        ));
+    }
+    let mut value = vec![0_u8; len];
+    cursor.read_exact(&mut value)?;
+    Ok(String::from_utf8(value)?)
+}
+
 #[cfg(test)]
 mod tests {
     use std::vec;
@@ -248,6 +566,102 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_merge_disjoint() {
+        let mut ownership = "src/main.rs:0-100\nsrc/main2.rs:0-10"
+            .parse::<Ownership>()
+            .unwrap();
+        ownership
+            .merge(
+                &"src/main.rs:200-300\nsrc/main3.rs:0-10"
+                    .parse::<Ownership>()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            ownership,
+            "src/main.rs:200-300,0-100\nsrc/main2.rs:0-10\nsrc/main3.rs:0-10"
+                .parse::<Ownership>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_same_hunk() {
+        let mut ownership = "src/main.rs:0-100".parse::<Ownership>().unwrap();
+        ownership
+            .merge(&"src/main.rs:0-100".parse::<Ownership>().unwrap())
+            .unwrap();
+        assert_eq!(ownership, "src/main.rs:0-100".parse::<Ownership>().unwrap());
+    }
+
+    #[test]
+    fn test_merge_overlapping() {
+        let mut ownership = "src/main.rs:0-100,200-300".parse::<Ownership>().unwrap();
+        let conflict = ownership
+            .merge(&"src/main.rs:50-150".parse::<Ownership>().unwrap())
+            .unwrap_err();
+        assert_eq!(
+            conflict.overlaps,
+            vec![(
+                "src/main.rs".into(),
+                "0-100".parse::<Hunk>().unwrap(),
+                "50-150".parse::<Hunk>().unwrap()
+            )]
+        );
+        // nothing is merged on conflict
+        assert_eq!(
+            ownership,
+            "src/main.rs:0-100,200-300".parse::<Ownership>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subtract_middle() {
+        let mut ownership = "src/main.rs:0-100\nsrc/main2.rs:0-100"
+            .parse::<Ownership>()
+            .unwrap();
+        ownership.subtract("src/main.rs", &"40-60".parse::<Hunk>().unwrap());
+        assert_eq!(
+            ownership,
+            "src/main.rs:0-39,61-100\nsrc/main2.rs:0-100"
+                .parse::<Ownership>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subtract_middle_single_line() {
+        let mut ownership = "src/main.rs:10-12".parse::<Ownership>().unwrap();
+        ownership.subtract("src/main.rs", &"11-11".parse::<Hunk>().unwrap());
+        assert_eq!(
+            ownership,
+            "src/main.rs:10-10,12-12".parse::<Ownership>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subtract_edges() {
+        let mut ownership = "src/main.rs:0-100,200-300".parse::<Ownership>().unwrap();
+        ownership.subtract("src/main.rs", &"50-250".parse::<Hunk>().unwrap());
+        assert_eq!(
+            ownership,
+            "src/main.rs:0-49,251-300".parse::<Ownership>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subtract_whole() {
+        let mut ownership = "src/main.rs:10-20\nsrc/main2.rs:0-100"
+            .parse::<Ownership>()
+            .unwrap();
+        ownership.subtract("src/main.rs", &"0-30".parse::<Hunk>().unwrap());
+        assert_eq!(
+            ownership,
+            "src/main2.rs:0-100".parse::<Ownership>().unwrap()
+        );
+    }
+
     #[test]
     fn test_equal() {
         for (a, b, expected) in vec![
@@ -285,4 +699,102 @@ mod tests {
             assert_eq!(a == b, expected, "{:#?} == {:#?}", a, b);
         }
     }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let ownership = "src/main.rs:0-100,200-300-abc-1710000000000\nsrc/lib.rs:1-2\nsrc/some:file.rs:5-10-def,20-30"
+            .parse::<Ownership>()
+            .unwrap();
+        assert_eq!(ownership.files.len(), 3);
+
+        let bytes = ownership.to_bytes().unwrap();
+        let decoded = Ownership::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, ownership);
+        // hunks compare equal regardless of their timestamps
+        assert_eq!(
+            decoded.files[0].hunks[1].timestamp_ms,
+            Some(1_710_000_000_000)
+        );
+
+        assert_eq!(
+            Ownership::from_bytes(&Ownership::default().to_bytes().unwrap()).unwrap(),
+            Ownership::default()
+        );
+        Ownership::from_bytes(b"not an ownership").unwrap_err();
+        Ownership::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+    }
+
+    #[test]
+    fn test_diff_added_removed() {
+        let ownership = "src/main.rs:0-100\nsrc/lib.rs:1-2"
+            .parse::<Ownership>()
+            .unwrap();
+        let other = "src/main.rs:0-100\nsrc/new.rs:5-10"
+            .parse::<Ownership>()
+            .unwrap();
+
+        let diff = ownership.diff(&other);
+        assert_eq!(
+            diff.added,
+            vec!["src/new.rs:5-10".parse::<FileOwnership>().unwrap()]
+        );
+        assert_eq!(
+            diff.removed,
+            vec!["src/lib.rs:1-2".parse::<FileOwnership>().unwrap()]
+        );
+        assert!(diff.modified.is_empty());
+
+        assert!(ownership.diff(&ownership).is_empty());
+        assert_eq!(
+            Ownership::default().diff(&ownership).added,
+            ownership.diff(&Ownership::default()).removed
+        );
+    }
+
+    #[test]
+    fn test_diff_modified() {
+        let ownership = "src/main.rs:0-100,200-300\nsrc/lib.rs:1-2"
+            .parse::<Ownership>()
+            .unwrap();
+        // main.rs keeps one hunk and trades the other, while lib.rs shares no hunks at all
+        let other = "src/main.rs:0-100,400-500\nsrc/lib.rs:10-20,30-40"
+            .parse::<Ownership>()
+            .unwrap();
+
+        let diff = ownership.diff(&other);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(
+            diff.modified,
+            vec![
+                FileOwnershipDiff {
+                    file_path: "src/lib.rs".into(),
+                    added_hunks: vec![Hunk::from(10..=20), Hunk::from(30..=40)],
+                    removed_hunks: vec![Hunk::from(1..=2)],
+                },
+                FileOwnershipDiff {
+                    file_path: "src/main.rs".into(),
+                    added_hunks: vec![Hunk::from(400..=500)],
+                    removed_hunks: vec![Hunk::from(200..=300)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_compares_ranges() {
+        // hashes and timestamps of hunks don't count as changes
+        let ownership = "src/main.rs:0-100-abc-1710000000000"
+            .parse::<Ownership>()
+            .unwrap();
+        let other = "src/main.rs:0-100-def".parse::<Ownership>().unwrap();
+        assert!(ownership.diff(&other).is_empty());
+
+        // files listed more than once are diffed with all of their hunks
+        let split = "src/main.rs:0-100\nsrc/main.rs:200-300"
+            .parse::<Ownership>()
+            .unwrap();
+        let joined = "src/main.rs:200-300,0-100".parse::<Ownership>().unwrap();
+        assert!(split.diff(&joined).is_empty());
+    }
 }
@@ -1,13 +1,18 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-use crate::{gb_repository, reader, writer};
+use crate::{gb_repository, reader, sessions, writer};
 
-use super::Branch;
+use super::{Branch, BranchId};
+
+// how many ownership files `BranchWriter::write_with_progress` writes between progress
+// reports, so that huge ownerships don't report once per file.
+const PROGRESS_INTERVAL: usize = 500;
 
 pub struct BranchWriter<'writer> {
     repository: &'writer gb_repository::Repository,
     writer: writer::DirWriter,
     reader: reader::Reader<'writer>,
+    binary_ownership: bool,
 }
 
 impl<'writer> BranchWriter<'writer> {
@@ -18,9 +23,18 @@ impl<'writer> BranchWriter<'writer> {
             repository,
             writer,
             reader,
+            binary_ownership: false,
         })
     }
 
+    /// Writes the ownership of branches as a compact binary `ownership.bin` instead of the
+    /// text `meta/ownership`, which is removed. Readers prefer `ownership.bin` when present,
+    /// so writing a branch without this switches it back to the hand-editable text format.
+    pub fn with_binary_ownership(mut self, binary_ownership: bool) -> Self {
+        self.binary_ownership = binary_ownership;
+        self
+    }
+
     pub fn delete(&self, branch: &Branch) -> Result<()> {
         match self
             .reader
@@ -39,14 +53,46 @@ impl<'writer> BranchWriter<'writer> {
     }
 
     pub fn write(&self, branch: &mut Branch) -> Result<()> {
+        self.write_with_progress(branch, |_, _| {})
+    }
+
+    /// Like `write`, but returns the session the branch was written in, and whether the write
+    /// had to create it, as `TargetWriter` does. Returns `None` if the branch was up to date.
+    pub fn write_reporting(
+        &self,
+        branch: &mut Branch,
+    ) -> Result<Option<(sessions::Session, bool)>> {
+        self.write_branch(branch, |_, _| {})
+    }
+
+    /// Like `write`, but calls `progress` with how many of the files the branch owns were
+    /// written so far, and how many it owns in total, every `PROGRESS_INTERVAL` files. The
+    /// last call reports all of them, once the branch is written, or found up to date.
+    pub fn write_with_progress(
+        &self,
+        branch: &mut Branch,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<()> {
+        self.write_branch(branch, progress).map(|_| ())
+    }
+
+    fn write_branch(
+        &self,
+        branch: &mut Branch,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Option<(sessions::Session, bool)>> {
+        let total = branch.ownership.files.len();
         let reader = self.reader.sub(format!("branches/{}", branch.id));
         match Branch::try_from(&reader) {
-            Ok(existing) if existing.eq(branch) => return Ok(()),
+            Ok(existing) if existing.eq(branch) => {
+                progress(total, total);
+                return Ok(None);
+            }
             Ok(_) | Err(reader::Error::NotFound) => {}
             Err(err) => return Err(err.into()),
         }
 
-        self.repository.mark_active_session()?;
+        let session = self.repository.mark_active_session_reporting()?;
 
         branch.updated_timestamp_ms = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
@@ -123,10 +169,35 @@ impl<'writer> BranchWriter<'writer> {
             branch.updated_timestamp_ms.to_string(),
         ));
 
-        batch.push(writer::BatchTask::Write(
-            format!("branches/{}/meta/ownership", branch.id),
-            branch.ownership.to_string(),
-        ));
+        // all files are reported as written only once they are
+        let mut encoded = |written: usize| {
+            if written % PROGRESS_INTERVAL == 0 && written < total {
+                progress(written, total);
+            }
+        };
+        if self.binary_ownership {
+            // written first, so that there's always an ownership to read if interrupted
+            self.writer.batch(&[writer::BatchTask::Write(
+                format!("branches/{}/ownership.bin", branch.id),
+                branch
+                    .ownership
+                    .to_bytes_with_progress(&mut encoded)
+                    .context("failed to encode ownership")?,
+            )])?;
+            batch.push(writer::BatchTask::Remove(format!(
+                "branches/{}/meta/ownership",
+                branch.id
+            )));
+        } else {
+            batch.push(writer::BatchTask::Write(
+                format!("branches/{}/meta/ownership", branch.id),
+                branch.ownership.to_string_with_progress(&mut encoded),
+            ));
+            batch.push(writer::BatchTask::Remove(format!(
+                "branches/{}/ownership.bin",
+                branch.id
+            )));
+        }
 
         if let Some(selected_for_changes) = branch.selected_for_changes {
             batch.push(writer::BatchTask::Write(
@@ -141,11 +212,44 @@ impl<'writer> BranchWriter<'writer> {
         }
 
         self.writer.batch(&batch)?;
+        progress(total, total);
 
-        Ok(())
+        Ok(Some(session))
     }
 }
 
+/// How much space a branch takes up in the gitbutler data directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Sums up the sizes of all files under `branches/<id>/`, including its target. Symlinks are
+/// not followed.
+pub fn branch_disk_usage(
+    repository: &gb_repository::Repository,
+    id: &BranchId,
+) -> Result<DiskUsage> {
+    let dir = repository.root().join("branches").join(id.to_string());
+    let mut usage = DiskUsage::default();
+    if !dir.exists() {
+        return Ok(usage);
+    }
+    for entry in walkdir::WalkDir::new(&dir) {
+        let entry = entry.with_context(|| format!("failed to walk {}", dir.display()))?;
+        let file_type = entry.file_type();
+        if !file_type.is_dir() && !file_type.is_symlink() {
+            usage.files += 1;
+            usage.bytes += entry
+                .metadata()
+                .with_context(|| format!("failed to stat {}", entry.path().display()))?
+                .len();
+        }
+    }
+    Ok(usage)
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -153,16 +257,13 @@ mod tests {
         sync::atomic::{AtomicUsize, Ordering},
     };
 
-    use anyhow::Context;
     use once_cell::sync::Lazy;
 
     use crate::{
         test_utils::{Case, Suite},
-        virtual_branches::branch,
+        virtual_branches::{branch, target},
     };
 
-    use self::branch::BranchId;
-
     use super::*;
 
     static TEST_INDEX: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
@@ -209,6 +310,63 @@ mod tests {
         }
     }
 
+    // sums up files the plain way, to check `branch_disk_usage` against.
+    fn walk(dir: &std::path::Path, usage: &mut DiskUsage) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                walk(&entry.path(), usage)?;
+            } else {
+                usage.files += 1;
+                usage.bytes += metadata.len();
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_disk_usage() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        BranchWriter::new(&gb_repository)?.write(&mut branch)?;
+        target::Writer::new(&gb_repository)?.write(
+            &branch.id,
+            &target::Target {
+                branch: "refs/remotes/origin/master".parse().unwrap(),
+                remote_url: "remote url".to_string(),
+                sha: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+                behind: 0,
+                push_remote_name: None,
+                push_remote_url: None,
+                last_fetched_sha: None,
+                sha_verified: None,
+                ref_kind: target::TargetRefKind::Branch,
+                ahead: None,
+                note: None,
+                pr_url: None,
+                pr_number: None,
+            },
+        )?;
+
+        let mut expected = DiskUsage::default();
+        walk(
+            &gb_repository
+                .root()
+                .join("branches")
+                .join(branch.id.to_string()),
+            &mut expected,
+        )?;
+
+        let usage = branch_disk_usage(&gb_repository, &branch.id)?;
+        assert!(usage.files > 0);
+        assert!(usage.bytes > 0);
+        assert_eq!(usage, expected);
+
+        Ok(())
+    }
+
     #[test]
     fn test_write_branch() -> Result<()> {
         let Case { gb_repository, .. } = Suite::default().new_case();
@@ -270,6 +428,77 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_write_binary_ownership() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = Branch {
+            ownership: "src/main.rs:0-100,200-300-abc\nsrc/lib.rs:1-2-def-1710000000000".parse()?,
+            ..test_branch()
+        };
+
+        BranchWriter::new(&gb_repository)?
+            .with_binary_ownership(true)
+            .write(&mut branch)?;
+
+        let root = gb_repository
+            .root()
+            .join("branches")
+            .join(branch.id.to_string());
+        assert!(root.join("ownership.bin").exists());
+        assert!(!root.join("meta").join("ownership").exists());
+
+        let reader = reader::Reader::open(gb_repository.root())?;
+        let read = Branch::try_from(&reader.sub(format!("branches/{}", branch.id)))?;
+        assert_eq!(read, branch);
+
+        // writing without it switches back to the text format
+        branch.name = "renamed".to_string();
+        BranchWriter::new(&gb_repository)?.write(&mut branch)?;
+        assert!(!root.join("ownership.bin").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("meta").join("ownership"))?,
+            branch.ownership.to_string()
+        );
+        let read = Branch::try_from(&reader.sub(format!("branches/{}", branch.id)))?;
+        assert_eq!(read, branch);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_with_progress() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        for binary_ownership in [false, true] {
+            let files = 10 * PROGRESS_INTERVAL + 1;
+            let mut branch = Branch {
+                ownership: branch::Ownership {
+                    files: (0..files)
+                        .map(|index| branch::FileOwnership {
+                            file_path: format!("src/file_{}.rs", index).into(),
+                            hunks: vec!["1-2".parse().unwrap()],
+                        })
+                        .collect(),
+                },
+                ..test_branch()
+            };
+
+            let mut calls = vec![];
+            BranchWriter::new(&gb_repository)?
+                .with_binary_ownership(binary_ownership)
+                .write_with_progress(&mut branch, |written, total| {
+                    calls.push((written, total));
+                })?;
+
+            assert_eq!(calls.last(), Some(&(files, files)));
+            assert_eq!(calls.len(), 11);
+            assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_should_create_session() -> Result<()> {
         let Case { gb_repository, .. } = Suite::default().new_case();
@@ -284,6 +513,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_write_reporting() -> Result<()> {
+        let target = target::Target {
+            branch: "refs/remotes/origin/master".parse().unwrap(),
+            remote_url: "remote url".to_string(),
+            sha: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: target::TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
+        };
+
+        // whichever writer writes first creates the session, and the other one reuses it
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let mut branch = test_branch();
+        let (session, created) = BranchWriter::new(&gb_repository)?
+            .write_reporting(&mut branch)?
+            .unwrap();
+        assert!(created);
+        let outcome = target::Writer::new(&gb_repository)?
+            .write(&branch.id, &target)?
+            .unwrap();
+        assert!(!outcome.session_created);
+        assert_eq!(outcome.session_id, session.id);
+
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let mut branch = test_branch();
+        let outcome = target::Writer::new(&gb_repository)?
+            .write(&branch.id, &target)?
+            .unwrap();
+        assert!(outcome.session_created);
+        let writer = BranchWriter::new(&gb_repository)?;
+        let (session, created) = writer.write_reporting(&mut branch)?.unwrap();
+        assert!(!created);
+        assert_eq!(session.id, outcome.session_id);
+
+        // writing an unchanged branch doesn't touch the session
+        assert!(writer.write_reporting(&mut branch)?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_should_update() -> Result<()> {
         let Case { gb_repository, .. } = Suite::default().new_case();
@@ -17,7 +17,7 @@ use super::{
         self, FetchFromTargetError, GetBaseBranchDataError, GetRemoteBranchDataError,
         IsRemoteBranchMergableError, ListRemoteBranchesError,
     },
-    target_to_base_branch, BaseBranch, RemoteBranchFile,
+    record_target_fetch, target_to_base_branch, BaseBranch, RemoteBranchFile,
 };
 
 #[derive(Clone)]
@@ -890,9 +890,16 @@ impl ControllerInner {
             .fetch(default_target.branch.remote(), &self.helper)
             .map_err(errors::FetchFromTargetError::Remote)
         {
-            Ok(()) => projects::FetchResult::Fetched {
-                timestamp: std::time::SystemTime::now(),
-            },
+            Ok(()) => {
+                if let Err(error) =
+                    record_target_fetch(&gb_repository, &project_repository, &default_target)
+                {
+                    tracing::warn!(%project_id, ?error, "failed to record fetched target");
+                }
+                projects::FetchResult::Fetched {
+                    timestamp: std::time::SystemTime::now(),
+                }
+            }
             Err(error) => projects::FetchResult::Error {
                 timestamp: std::time::SystemTime::now(),
                 error: error.to_string(),
@@ -128,6 +128,16 @@ mod tests {
             )
             .parse()
             .unwrap(),
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: target::TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
         }
     }
 
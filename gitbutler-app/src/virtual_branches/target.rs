@@ -1,18 +1,74 @@
+mod encryption;
+mod git_config;
+mod pack;
 mod reader;
+mod watcher;
 mod writer;
 
-use serde::{ser::SerializeStruct, Serialize, Serializer};
+use anyhow::{anyhow, Context, Result};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 
-pub use reader::TargetReader as Reader;
-pub use writer::TargetWriter as Writer;
+pub use encryption::EncryptionKey;
+pub use git_config::GitConfigTargetWriter;
+pub use reader::{IntegrityError, RawTargetNames, TargetReader as Reader, TargetTimestamps};
+pub use watcher::TargetWatcher;
+pub use writer::{
+    AllTargets, BestEffortWrite, ConsistencyReport, LockHook, MigrationReport, RemoteRef,
+    RemoteRemoval, RetryPolicy, ShaRepair, TargetChanged, TargetHistoryEntry,
+    TargetWriter as Writer, WriteOutcome, DEFAULT_HISTORY_LIMIT,
+};
 
 use crate::git;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Target {
     pub branch: git::RemoteRefname,
     pub remote_url: String,
     pub sha: git::Oid,
+    // number of upstream commits the target is behind, as of the last time it was computed
+    pub behind: usize,
+    // where to push to, if not to the remote the target is fetched from, e.g. a personal fork
+    pub push_remote_name: Option<String>,
+    pub push_remote_url: Option<String>,
+    // the remote tip as of the last fetch, while `sha` stays the base virtual branches are
+    // applied on. `behind` is counted between the two.
+    pub last_fetched_sha: Option<git::Oid>,
+    // whether the commit at `sha` had a valid signature when the target was recorded, `None`
+    // if unknown, e.g. because it was never checked or the signature can't be verified.
+    pub sha_verified: Option<bool>,
+    // whether `branch` names a branch of the remote or one of its tags
+    pub ref_kind: TargetRefKind,
+    // number of local commits the virtual branch has on top of `sha`, if it was computed
+    pub ahead: Option<usize>,
+    // why the branch tracks this target, as the user put it. at most `MAX_NOTE_LEN` bytes.
+    pub note: Option<String>,
+    // the open pull or merge request of the branch, if it's associated with one. the url is
+    // http(s).
+    pub pr_url: Option<String>,
+    pub pr_number: Option<u64>,
+}
+
+/// The longest note a target can be written with, in bytes.
+pub const MAX_NOTE_LEN: usize = 4096;
+
+/// Whether a target tracks a branch of its remote, which moves as commits are pushed, or a
+/// tag, e.g. of a release, which is a fixed point.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum TargetRefKind {
+    #[default]
+    Branch,
+    Tag,
+}
+
+/// The ref a target tracks, by its short name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetRef {
+    Branch(String),
+    Tag(String),
 }
 
 impl Serialize for Target {
@@ -20,24 +76,739 @@ impl Serialize for Target {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Target", 5)?;
+        let mut state = serializer.serialize_struct("Target", 14)?;
         state.serialize_field("branchName", &self.branch.branch())?;
         state.serialize_field("remoteName", &self.branch.remote())?;
         state.serialize_field("remoteUrl", &self.remote_url)?;
         state.serialize_field("sha", &self.sha.to_string())?;
+        state.serialize_field("behind", &self.behind)?;
+        state.serialize_field("pushRemoteName", &self.push_remote_name)?;
+        state.serialize_field("pushRemoteUrl", &self.push_remote_url)?;
+        state.serialize_field(
+            "lastFetchedSha",
+            &self.last_fetched_sha.map(|sha| sha.to_string()),
+        )?;
+        state.serialize_field("shaVerified", &self.sha_verified)?;
+        state.serialize_field("refKind", &self.ref_kind)?;
+        state.serialize_field("ahead", &self.ahead)?;
+        state.serialize_field("note", &self.note)?;
+        state.serialize_field("prUrl", &self.pr_url)?;
+        state.serialize_field("prNumber", &self.pr_number)?;
         state.end()
     }
 }
 
+// a target as its `Serialize` implementation writes it.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedTarget {
+    branch_name: String,
+    remote_name: String,
+    remote_url: String,
+    sha: git::Oid,
+    #[serde(default)]
+    behind: usize,
+    #[serde(default)]
+    push_remote_name: Option<String>,
+    #[serde(default)]
+    push_remote_url: Option<String>,
+    #[serde(default)]
+    last_fetched_sha: Option<git::Oid>,
+    #[serde(default)]
+    sha_verified: Option<bool>,
+    #[serde(default)]
+    ref_kind: TargetRefKind,
+    #[serde(default)]
+    ahead: Option<usize>,
+    #[serde(default)]
+    note: Option<String>,
+    #[serde(default)]
+    pr_url: Option<String>,
+    #[serde(default)]
+    pr_number: Option<u64>,
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let target = SerializedTarget::deserialize(deserializer)?;
+        Ok(Target {
+            branch: git::RemoteRefname::new(&target.remote_name, &target.branch_name),
+            remote_url: target.remote_url,
+            sha: target.sha,
+            behind: target.behind,
+            push_remote_name: target.push_remote_name,
+            push_remote_url: target.push_remote_url,
+            last_fetched_sha: target.last_fetched_sha,
+            sha_verified: target.sha_verified,
+            ref_kind: target.ref_kind,
+            ahead: target.ahead,
+            note: target.note,
+            pr_url: target.pr_url,
+            pr_number: target.pr_number,
+        })
+    }
+}
+
+/// A field that differs between two targets, with the values as they are persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetFieldDiff {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TargetError {
+    #[error("repository is locked by another writer")]
+    Locked,
+    #[error("repository is locked on behalf of process {owner_pid}, which is not running")]
+    StaleLock { owner_pid: u32 },
+    #[error("failed to get or create current session")]
+    Session(#[source] anyhow::Error),
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+    #[error("target was written since version {expected}, it's at version {actual}")]
+    VersionConflict { expected: u64, actual: u64 },
+    #[error("malformed sha {value} in {}", target_name(branch_id.as_ref()))]
+    MalformedSha {
+        /// The branch whose target has the sha, or none for the default target.
+        branch_id: Option<crate::virtual_branches::BranchId>,
+        value: String,
+    },
+    #[error("target field {field} is larger than {max_size} bytes")]
+    FieldTooLarge { field: String, max_size: usize },
+    #[error("branch {0} has no target of its own")]
+    SourceMissing(crate::virtual_branches::BranchId),
+    #[error("branch {branch} does not exist on remote {remote}")]
+    NoSuchUpstream { remote: String, branch: String },
+    #[error("no remote named {0}")]
+    UnknownRemote(String),
+    #[error("invalid remote name {0}")]
+    InvalidRemoteName(String),
+    #[error("no remote tracking ref {0}")]
+    NoRemoteTracking(String),
+    #[error("target sha {sha} and branch head {head} share no history")]
+    UnrelatedHistories { sha: git::Oid, head: git::Oid },
+    #[error("note is {len} bytes long, at most {} are allowed", MAX_NOTE_LEN)]
+    NoteTooLong { len: usize },
+    #[error("pull request url {0} is not an http(s) url")]
+    InvalidPrUrl(String),
+    #[error("branch directory {existing_id} is the one of branch {new_id} but for case")]
+    CaseCollision {
+        /// The name of the directory already under `branches/`, as it is on disk.
+        existing_id: String,
+        new_id: crate::virtual_branches::BranchId,
+    },
+    #[error("failed to list branches of remote {remote}")]
+    RemoteUnreachable {
+        remote: String,
+        source: crate::git::Error,
+    },
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for TargetError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<TargetError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        match error.downcast::<crate::reader::Error>() {
+            Ok(error) => error.into(),
+            Err(error) => TargetError::Other(error),
+        }
+    }
+}
+
+impl From<crate::reader::Error> for TargetError {
+    fn from(error: crate::reader::Error) -> Self {
+        // malformed shas and oversized fields are reported by the reader as invalid data
+        // wrapping this error
+        if let crate::reader::Error::Io(io_error) = &error {
+            match io_error
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<TargetError>())
+            {
+                Some(TargetError::MalformedSha { branch_id, value }) => {
+                    return TargetError::MalformedSha {
+                        branch_id: *branch_id,
+                        value: value.clone(),
+                    };
+                }
+                Some(TargetError::FieldTooLarge { field, max_size }) => {
+                    return TargetError::FieldTooLarge {
+                        field: field.clone(),
+                        max_size: *max_size,
+                    };
+                }
+                _ => {}
+            }
+        }
+        TargetError::Other(error.into())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BehindError {
+    #[error("target sha {sha} is not reachable from the remote tip {tip}")]
+    Diverged { sha: git::Oid, tip: git::Oid },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// directory holding the default target of the given remote. these are kept out of
+// `branches/target/`, which is replaced as a whole whenever the primary default is written.
+fn remote_default_dir(remote_name: &str) -> std::io::Result<String> {
+    if remote_name.is_empty()
+        || remote_name == "."
+        || remote_name == ".."
+        || remote_name.contains(['/', '\\'])
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid remote name: {:?}", remote_name),
+        ));
+    }
+    Ok(format!("branches/remotes/{}", remote_name))
+}
+
+fn validate_url(remote_url: &str) -> Result<()> {
+    let url = remote_url
+        .parse::<git::Url>()
+        .with_context(|| format!("invalid remote url {:?}", remote_url))?;
+    if let git::Scheme::Ext(scheme) = url.scheme {
+        return Err(anyhow!(
+            "unsupported scheme {:?} in remote url {:?}",
+            scheme,
+            remote_url
+        ));
+    }
+    Ok(())
+}
+
+// files a target is read from in the one file per field layout, including those of older
+// formats.
+const FIELD_FILES: [&str; 15] = [
+    "name",
+    "branch_name",
+    "remote",
+    "remote_url",
+    "sha",
+    "behind",
+    "push_remote_name",
+    "push_remote_url",
+    "last_fetched_sha",
+    "sha_verified",
+    "ref_kind",
+    "ahead",
+    "note",
+    "pr_url",
+    "pr_number",
+];
+
+// the most that is read of a field file, so that a corrupt or malicious one isn't read whole.
+// shas are far shorter than names and urls.
+const MAX_FIELD_SIZE: usize = 64 * 1024;
+const MAX_SHA_FIELD_SIZE: usize = 64;
+
+fn max_field_size(field: &str) -> usize {
+    match field {
+        "sha" | "last_fetched_sha" => MAX_SHA_FIELD_SIZE,
+        _ => MAX_FIELD_SIZE,
+    }
+}
+
+// how much is read of the file at path if it's a field file of a target, for
+// `reader::Reader::batch_limited`.
+fn field_size_limit(path: &std::path::Path) -> Option<usize> {
+    let field = path.file_name()?.to_str()?;
+    let is_field = path.parent()?.file_name()? == "target" && FIELD_FILES.contains(&field);
+    is_field.then(|| max_field_size(field))
+}
+
+// stands in for remote urls in redacted snapshots and dumps, as they may embed credentials.
+pub(super) const REDACTED_URL: &str = "<redacted>";
+
+// files kept next to the fields of a target in the one file per field layout, which are not
+// fields themselves.
+const TIMESTAMP_FILES: [&str; 2] = ["created_timestamp_ms", "updated_timestamp_ms"];
+// when the target was last fetched, kept next to the timestamps.
+const FETCHED_TIMESTAMP_FILE: &str = "last_fetched_ms";
+// when the target was last checked without changing it, see `Writer::touch`.
+const CHECKED_TIMESTAMP_FILE: &str = ".last_checked_ms";
+
+// checksum over the field files of a target, independent of their order.
+fn checksum(files: &[(String, String)]) -> String {
+    let mut files = files.iter().collect::<Vec<_>>();
+    files.sort();
+    let mut hasher = Sha256::new();
+    for (name, contents) in files {
+        hasher.update(name.as_bytes());
+        hasher.update([0_u8]);
+        hasher.update(contents.as_bytes());
+        hasher.update([0_u8]);
+    }
+    format!("{:X}", hasher.finalize())
+}
+
+// on-disk representation of a target in the single file `target.json` layout
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TargetFile {
+    branch: git::RemoteRefname,
+    remote_url: String,
+    sha: git::Oid,
+    #[serde(default)]
+    behind: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    push_remote_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    push_remote_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_fetched_sha: Option<git::Oid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "is_branch")]
+    ref_kind: TargetRefKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ahead: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pr_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pr_number: Option<u64>,
+}
+
+// targets of branches are stored as they were before tags could be targets. takes a
+// reference, as serde requires.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_branch(ref_kind: &TargetRefKind) -> bool {
+    *ref_kind == TargetRefKind::Branch
+}
+
+impl From<&Target> for TargetFile {
+    fn from(target: &Target) -> Self {
+        Self {
+            branch: target.branch.clone(),
+            remote_url: target.remote_url.clone(),
+            sha: target.sha,
+            behind: target.behind,
+            push_remote_name: target.push_remote_name.clone(),
+            push_remote_url: target.push_remote_url.clone(),
+            last_fetched_sha: target.last_fetched_sha,
+            sha_verified: target.sha_verified,
+            ref_kind: target.ref_kind,
+            ahead: target.ahead,
+            note: target.note.clone(),
+            pr_url: target.pr_url.clone(),
+            pr_number: target.pr_number,
+        }
+    }
+}
+
+impl From<TargetFile> for Target {
+    fn from(file: TargetFile) -> Self {
+        Self {
+            branch: file.branch,
+            remote_url: file.remote_url,
+            sha: file.sha,
+            behind: file.behind,
+            push_remote_name: file.push_remote_name,
+            push_remote_url: file.push_remote_url,
+            last_fetched_sha: file.last_fetched_sha,
+            sha_verified: file.sha_verified,
+            ref_kind: file.ref_kind,
+            ahead: file.ahead,
+            note: file.note,
+            pr_url: file.pr_url,
+            pr_number: file.pr_number,
+        }
+    }
+}
+
+/// Targets are ordered by the remote and branch they track, then by sha. The other fields
+/// only break ties, so that targets are only equal in order if they are equal.
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.branch
+            .remote()
+            .cmp(other.branch.remote())
+            .then_with(|| self.branch.branch().cmp(other.branch.branch()))
+            .then_with(|| self.sha.cmp(&other.sha))
+            .then_with(|| self.remote_url.cmp(&other.remote_url))
+            .then_with(|| self.behind.cmp(&other.behind))
+            .then_with(|| self.push_remote_name.cmp(&other.push_remote_name))
+            .then_with(|| self.push_remote_url.cmp(&other.push_remote_url))
+            .then_with(|| self.last_fetched_sha.cmp(&other.last_fetched_sha))
+            .then_with(|| self.sha_verified.cmp(&other.sha_verified))
+            .then_with(|| self.ref_kind.cmp(&other.ref_kind))
+            .then_with(|| self.ahead.cmp(&other.ahead))
+            .then_with(|| self.note.cmp(&other.note))
+            .then_with(|| self.pr_url.cmp(&other.pr_url))
+            .then_with(|| self.pr_number.cmp(&other.pr_number))
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Target {
+    /// Checks that the target can be persisted, i.e. that its remote url is something git
+    /// can fetch from: https, ssh, git, file or a scp-like `user@host:path`.
+    /// The same goes for the push remote url, if any. The remote names must pass
+    /// `validate_remote_name`, the note, if any, must be at most `MAX_NOTE_LEN` bytes, and the
+    /// pull request url, if any, must be http(s).
+    pub fn validate(&self) -> Result<()> {
+        // as it is written
+        let target = self.normalized();
+        Self::validate_remote_name(target.branch.remote())?;
+        validate_url(&target.remote_url)?;
+        if let Some(push_remote_name) = &target.push_remote_name {
+            Self::validate_remote_name(push_remote_name)?;
+        }
+        if let Some(push_remote_url) = &target.push_remote_url {
+            validate_url(push_remote_url)?;
+        }
+        if let Some(note) = &target.note {
+            if note.len() > MAX_NOTE_LEN {
+                return Err(TargetError::NoteTooLong { len: note.len() }.into());
+            }
+        }
+        if let Some(pr_url) = &target.pr_url {
+            let is_http = url::Url::parse(pr_url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some());
+            if !is_http {
+                return Err(TargetError::InvalidPrUrl(pr_url.clone()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `remote_name` is a name git can look a remote up by in its config: it
+    /// must not be empty, nor contain slashes or control characters. Fails with
+    /// `TargetError::InvalidRemoteName` otherwise.
+    pub fn validate_remote_name(remote_name: &str) -> Result<(), TargetError> {
+        if remote_name.is_empty() || remote_name.contains(|c: char| c == '/' || c.is_control()) {
+            return Err(TargetError::InvalidRemoteName(remote_name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Builds a target tracking `branch_name` on `remote_name` as `repo` knows it: the remote
+    /// url is taken from the git config and the sha is the commit the remote tracking branch
+    /// points to, which the target is not behind yet. Fails with `TargetError::UnknownRemote`
+    /// if `repo` has no such remote, and with `TargetError::NoSuchUpstream` if it has no
+    /// remote tracking branch of that name.
+    pub fn from_upstream(
+        repo: &git::Repository,
+        remote_name: &str,
+        branch_name: &str,
+    ) -> Result<Target, TargetError> {
+        let remote = match repo.find_remote(remote_name) {
+            Ok(remote) => remote,
+            Err(git::Error::NotFound(_)) => {
+                return Err(TargetError::UnknownRemote(remote_name.to_string()))
+            }
+            Err(error) => {
+                return Err(anyhow::Error::from(error)
+                    .context(format!("failed to find remote {}", remote_name))
+                    .into())
+            }
+        };
+        let remote_url = remote
+            .url_as_str()
+            .with_context(|| format!("failed to get url of remote {}", remote_name))?
+            .with_context(|| format!("remote {} has no url", remote_name))?
+            .to_string();
+
+        let branch = git::RemoteRefname::new(remote_name, branch_name);
+        let sha = match repo.find_branch(&branch.clone().into()) {
+            Ok(upstream) => upstream
+                .peel_to_commit()
+                .with_context(|| format!("failed to peel branch {} to commit", branch))?
+                .id(),
+            Err(git::Error::NotFound(_)) => {
+                return Err(TargetError::NoSuchUpstream {
+                    remote: remote_name.to_string(),
+                    branch: branch_name.to_string(),
+                })
+            }
+            Err(error) => {
+                return Err(anyhow::Error::from(error)
+                    .context(format!("failed to find branch {}", branch))
+                    .into())
+            }
+        };
+
+        Ok(Target {
+            branch,
+            remote_url,
+            sha,
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
+        })
+    }
+
+    /// Expands a possibly abbreviated sha to the full id of the commit it names in `repo`, so
+    /// that only canonical shas get stored. Fails if no commit, or more than one, matches.
+    pub fn resolve_sha(repo: &git::Repository, sha: &str) -> Result<git::Oid> {
+        repo.find_commit_by_prefix(sha)
+            .map(|commit| commit.id())
+            .with_context(|| format!("failed to resolve sha {}", sha))
+    }
+
+    /// Checks whether the commit at the target's sha has a valid signature, to be recorded as
+    /// `sha_verified`. Unsigned commits are not verified. Only ssh signatures can be checked,
+    /// so for others, such as gpg ones, it's unknown.
+    pub fn verify_signature(&self, repo: &git::Repository) -> Result<Option<bool>> {
+        repo.find_commit(self.sha)
+            .with_context(|| format!("failed to find commit {}", self.sha))?;
+        let Some((signature, signed_data)) = repo
+            .extract_signature(self.sha)
+            .with_context(|| format!("failed to extract signature of {}", self.sha))?
+        else {
+            return Ok(Some(false));
+        };
+        if !signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+            return Ok(None);
+        }
+        // a signature that doesn't even parse is not a valid one
+        Ok(Some(
+            crate::keys::verify(&signature, &signed_data).unwrap_or(false),
+        ))
+    }
+
+    /// Counts the commits the remote tip of the target branch is ahead of the stored sha.
+    /// Tags are fixed points, so a tag target is only behind if the tag was moved since.
+    pub fn compute_behind(&self, repo: &git::Repository) -> Result<usize, BehindError> {
+        count_behind(repo, self.sha, self.tip(repo)?)
+    }
+
+    /// Counts the commits `branch_head` has on top of the stored sha, i.e. the local commits
+    /// of a virtual branch based on the target, to be recorded as `ahead`.
+    pub fn compute_ahead(&self, repo: &git::Repository, branch_head: git::Oid) -> Result<usize> {
+        count_commits(repo, self.sha, branch_head)
+    }
+
+    /// The commit the tracked ref points to in `repo`: the remote tracking branch of a branch
+    /// target, or the tag of a tag target.
+    pub fn tip(&self, repo: &git::Repository) -> Result<git::Oid> {
+        let tip = match self.target_ref() {
+            TargetRef::Branch(_) => repo
+                .find_branch(&self.branch.clone().into())
+                .with_context(|| format!("failed to find branch {}", self.branch))?
+                .peel_to_commit()
+                .with_context(|| format!("failed to peel branch {} to commit", self.branch))?,
+            TargetRef::Tag(tag) => repo
+                .find_reference(&git::Refname::Other(format!("refs/tags/{}", tag)))
+                .with_context(|| format!("failed to find tag {}", tag))?
+                .peel_to_commit()
+                .with_context(|| format!("failed to peel tag {} to commit", tag))?,
+        };
+        Ok(tip.id())
+    }
+
+    /// The branch or tag the target tracks, without its remote.
+    pub fn target_ref(&self) -> TargetRef {
+        let name = self.normalized().branch.branch().to_string();
+        match self.ref_kind {
+            TargetRefKind::Branch => TargetRef::Branch(name),
+            TargetRefKind::Tag => TargetRef::Tag(name),
+        }
+    }
+
+    /// Counts the commits the last fetched tip of the target branch is ahead of the stored
+    /// sha, without looking at the remote branch. Zero if the target was never fetched.
+    pub fn compute_behind_fetched(&self, repo: &git::Repository) -> Result<usize, BehindError> {
+        match self.last_fetched_sha {
+            Some(last_fetched_sha) => count_behind(repo, self.sha, last_fetched_sha),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the fully qualified `refs/remotes/<remote>/<branch>` name of the target branch,
+    /// or `refs/tags/<tag>` for a tag.
+    pub fn canonical_ref(&self) -> String {
+        match self.target_ref() {
+            TargetRef::Branch(_) => self.normalized().branch.to_string(),
+            TargetRef::Tag(tag) => format!("refs/tags/{}", tag),
+        }
+    }
+
+    /// Lists the fields that differ from `other`, taking this target as the old one.
+    pub fn diff(&self, other: &Target) -> Vec<TargetFieldDiff> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((field, old), (_, new))| TargetFieldDiff {
+                field,
+                old: old.unwrap_or_default(),
+                new: new.unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    // the fields of the target, as they are persisted one file per field. optional fields
+    // that are not set are not persisted, and neither is the kind of branch targets.
+    fn fields(&self) -> [(&'static str, Option<String>); 14] {
+        [
+            (
+                "branch_name",
+                Some(format!("{}/{}", self.branch.remote(), self.branch.branch())),
+            ),
+            ("remote_name", Some(self.branch.remote().to_string())),
+            ("remote_url", Some(self.remote_url.clone())),
+            ("sha", Some(self.sha.to_string())),
+            ("behind", Some(self.behind.to_string())),
+            ("push_remote_name", self.push_remote_name.clone()),
+            ("push_remote_url", self.push_remote_url.clone()),
+            (
+                "last_fetched_sha",
+                self.last_fetched_sha.map(|sha| sha.to_string()),
+            ),
+            (
+                "sha_verified",
+                self.sha_verified.map(|verified| verified.to_string()),
+            ),
+            (
+                "ref_kind",
+                (self.ref_kind == TargetRefKind::Tag).then(|| "tag".to_string()),
+            ),
+            ("ahead", self.ahead.map(|ahead| ahead.to_string())),
+            ("note", self.note.clone()),
+            ("pr_url", self.pr_url.clone()),
+            ("pr_number", self.pr_number.map(|number| number.to_string())),
+        ]
+    }
+
+    // returns the target with the bare remote name and the short branch name, regardless of
+    // whether the caller passed them fully qualified or not. single line fields are stripped
+    // of trailing whitespace, such as the `\r` of a windows line ending, which would keep
+    // refs from matching. the note may span lines, and is kept as is.
+    fn normalized(&self) -> Target {
+        let remote = self.branch.remote().trim_end();
+        let remote = remote.strip_prefix("refs/remotes/").unwrap_or(remote);
+
+        let branch = self.branch.branch().trim_end();
+        let branch = branch
+            .strip_prefix(&format!("refs/remotes/{}/", remote))
+            .or_else(|| branch.strip_prefix("refs/heads/"))
+            .unwrap_or(branch);
+
+        let trimmed =
+            |value: &Option<String>| value.as_deref().map(|value| value.trim_end().to_string());
+        Target {
+            branch: git::RemoteRefname::new(remote, branch),
+            remote_url: self.remote_url.trim_end().to_string(),
+            push_remote_name: trimmed(&self.push_remote_name),
+            push_remote_url: trimmed(&self.push_remote_url),
+            pr_url: trimmed(&self.pr_url),
+            ..self.clone()
+        }
+    }
+
+    fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&TargetFile::from(self))
+    }
+
+    fn from_json(content: &crate::reader::Content) -> Result<Target, crate::reader::Error> {
+        let json: String = content.try_into()?;
+        serde_json::from_str::<TargetFile>(&json)
+            .map(Into::into)
+            .map_err(|e| {
+                crate::reader::Error::Io(
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("target.json: {}", e),
+                    )
+                    .into(),
+                )
+            })
+    }
+
+    // reads a target stored under `dir`, preferring `target.json` over the legacy one file per
+    // field `target/` layout.
+    fn read_from(
+        reader: &crate::reader::Reader,
+        dir: &str,
+    ) -> Result<Target, crate::reader::Error> {
+        match reader.read(format!("{}/target.json", dir)) {
+            Ok(content) => Self::from_json(&content),
+            Err(crate::reader::Error::NotFound) => {
+                match Self::try_from(&reader.sub(format!("{}/target", dir))) {
+                    // a write interrupted between the renames of `replace_dir` leaves the
+                    // previous fields in `target.old` only
+                    Err(crate::reader::Error::NotFound) => {
+                        Self::try_from(&reader.sub(format!("{}/target.old", dir)))
+                    }
+                    result => result,
+                }
+            }
+            Err(error) => Err(error),
+        }
+    }
+
     fn try_from(reader: &crate::reader::Reader) -> Result<Target, crate::reader::Error> {
-        let results = reader.batch(&["name", "branch_name", "remote", "remote_url", "sha"])?;
+        let results = reader.batch_limited(&FIELD_FILES, &field_size_limit)?;
+        Self::from_field_files(Some(reader), &results)
+    }
+
+    // builds the target from the contents of `FIELD_FILES`, in order, as read from `reader`.
+    // without a reader, as for targets not stored in files, binary contents can't be read.
+    fn from_field_files(
+        reader: Option<&crate::reader::Reader>,
+        results: &[Result<crate::reader::Content, crate::reader::Error>],
+    ) -> Result<Target, crate::reader::Error> {
+        if let Some(field) = FIELD_FILES.iter().zip(results).find_map(|(field, result)| {
+            matches!(result, Ok(crate::reader::Content::Large)).then_some(*field)
+        }) {
+            return Err(crate::reader::Error::Io(
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    TargetError::FieldTooLarge {
+                        field: field.to_string(),
+                        max_size: max_field_size(field),
+                    },
+                )
+                .into(),
+            ));
+        }
 
         let name = results[0].clone();
         let branch_name = results[1].clone();
         let remote = results[2].clone();
         let remote_url = results[3].clone();
         let sha = results[4].clone();
+        let behind = results[5].clone();
+        let push_remote_name = results[6].clone();
+        let push_remote_url = results[7].clone();
+        let last_fetched_sha = results[8].clone();
+        let sha_verified = results[9].clone();
+        let ref_kind = results[10].clone();
+        let ahead = results[11].clone();
+        let note = results[12].clone();
+        let pr_url = results[13].clone();
+        let pr_number = results[14].clone();
 
         let branch_name = match name {
             Ok(branch) => {
@@ -45,9 +816,15 @@ impl Target {
                 Ok(branch.clone())
             }
             Err(crate::reader::Error::NotFound) => {
-                // fallback to the old format
-                let branch_name: String = branch_name?.try_into()?;
-                Ok(branch_name)
+                // fallback to the old format. names that aren't valid UTF-8, as written by
+                // `Writer::write_raw`, are read lossily.
+                match (branch_name?, reader) {
+                    (crate::reader::Content::Binary, Some(reader)) => Ok(String::from_utf8_lossy(
+                        &reader.read_bytes("branch_name")?,
+                    )
+                    .into_owned()),
+                    (branch_name, _) => Ok(branch_name.try_into()?),
+                }
             }
             Err(e) => Err(crate::reader::Error::Io(
                 std::io::Error::new(std::io::ErrorKind::Other, format!("branch: {}", e)).into(),
@@ -63,17 +840,640 @@ impl Target {
             )),
         }?;
 
-        let sha: String = sha?.try_into()?;
-        let sha = sha.parse().map_err(|e| {
-            crate::reader::Error::Io(
-                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("sha: {}", e)).into(),
-            )
-        })?;
+        let sha = parse_sha(sha?.try_into()?)?;
+        // absent until the target's remote is fetched
+        let last_fetched_sha = match last_fetched_sha {
+            Ok(sha) => Some(parse_sha(sha.try_into()?)?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        // absent if it's unknown whether the sha is signed
+        let sha_verified: Option<bool> = match sha_verified {
+            Ok(verified) => Some(verified.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        // absent for branches, which were the only targets before tags
+        let ref_kind = match ref_kind {
+            Ok(kind) => match String::try_from(kind)?.as_str() {
+                "branch" => TargetRefKind::Branch,
+                "tag" => TargetRefKind::Tag,
+                kind => {
+                    return Err(crate::reader::Error::Io(
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("ref_kind: unknown kind {:?}", kind),
+                        )
+                        .into(),
+                    ))
+                }
+            },
+            Err(crate::reader::Error::NotFound) => TargetRefKind::Branch,
+            Err(error) => return Err(error),
+        };
+
+        let behind: usize = match behind {
+            Ok(behind) => Ok(behind.try_into()?),
+            // older repositories did not persist it
+            Err(crate::reader::Error::NotFound) => Ok(0),
+            Err(error) => Err(error),
+        }?;
+        // absent until it's computed for the branch
+        let ahead: Option<usize> = match ahead {
+            Ok(ahead) => Some(ahead.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        // absent unless the user annotated the target
+        let note: Option<String> = match note {
+            Ok(note) => Some(note.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        // absent unless the branch is associated with a pull request
+        let pr_url: Option<String> = match pr_url {
+            Ok(url) => Some(url.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        let pr_number: Option<u64> = match pr_number {
+            Ok(number) => Some(number.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+
+        // absent unless the target pushes to a different remote than it fetches from
+        let push_remote_name: Option<String> = match push_remote_name {
+            Ok(name) => Some(name.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+        let push_remote_url: Option<String> = match push_remote_url {
+            Ok(url) => Some(url.try_into()?),
+            Err(crate::reader::Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+
+        let branch = format!("refs/remotes/{}", branch_name)
+            .parse()
+            .map_err(|e| {
+                crate::reader::Error::Io(
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("branch_name: {}", e),
+                    )
+                    .into(),
+                )
+            })?;
 
         Ok(Self {
-            branch: format!("refs/remotes/{}", branch_name).parse().unwrap(),
+            branch,
             remote_url,
             sha,
+            behind,
+            push_remote_name,
+            push_remote_url,
+            last_fetched_sha,
+            sha_verified,
+            ref_kind,
+            ahead,
+            note,
+            pr_url,
+            pr_number,
         })
     }
 }
+
+// counts the commits `tip` is ahead of `sha`, failing if `tip` doesn't descend from it.
+fn count_behind(
+    repo: &git::Repository,
+    sha: git::Oid,
+    tip: git::Oid,
+) -> Result<usize, BehindError> {
+    if tip == sha {
+        return Ok(0);
+    }
+
+    // the stored sha is not in the remote history anymore, e.g. after a force push
+    match repo.is_descendant_of(tip, sha) {
+        Ok(true) => {}
+        Ok(false) | Err(git::Error::NotFound(_)) => return Err(BehindError::Diverged { sha, tip }),
+        Err(error) => return Err(BehindError::Other(error.into())),
+    }
+
+    Ok(count_commits(repo, sha, tip)?)
+}
+
+// counts the commits reachable from `tip` but not from `sha`.
+fn count_commits(repo: &git::Repository, sha: git::Oid, tip: git::Oid) -> Result<usize> {
+    let mut revwalk = repo.revwalk().context("failed to create revwalk")?;
+    revwalk
+        .push(tip.into())
+        .with_context(|| format!("failed to push {} to revwalk", tip))?;
+    revwalk
+        .hide(sha.into())
+        .with_context(|| format!("failed to hide {} from revwalk", sha))?;
+    let commits = revwalk
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to walk commits")?;
+
+    Ok(commits.len())
+}
+
+// parses a persisted sha, failing with `MalformedSha` unless it's a full 40 character hex sha.
+// shorter values would otherwise be padded with zeros into a different sha. shas are always
+// written lowercase, so uppercase hex is rejected too.
+fn parse_sha(sha: String) -> Result<git::Oid, crate::reader::Error> {
+    if sha.len() != 40
+        || !sha
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    {
+        return Err(malformed_sha(None, sha));
+    }
+    sha.parse().map_err(|_| malformed_sha(None, sha))
+}
+
+// the error the reader reports a malformed sha with.
+fn malformed_sha(
+    branch_id: Option<crate::virtual_branches::BranchId>,
+    value: String,
+) -> crate::reader::Error {
+    crate::reader::Error::Io(
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            TargetError::MalformedSha { branch_id, value },
+        )
+        .into(),
+    )
+}
+
+// attributes a malformed sha in an error of the reader to the branch whose target was read.
+fn with_branch_id(
+    error: crate::reader::Error,
+    id: &crate::virtual_branches::BranchId,
+) -> crate::reader::Error {
+    match TargetError::from(error.clone()) {
+        TargetError::MalformedSha {
+            branch_id: None,
+            value,
+        } => malformed_sha(Some(*id), value),
+        _ => error,
+    }
+}
+
+fn target_name(branch_id: Option<&crate::virtual_branches::BranchId>) -> String {
+    match branch_id {
+        Some(id) => format!("the target of branch {}", id),
+        None => "the default target".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils;
+
+    fn target_with_url(remote_url: &str) -> Target {
+        Target {
+            branch: "refs/remotes/origin/master".parse().unwrap(),
+            remote_url: remote_url.to_string(),
+            sha: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_empty_url() {
+        target_with_url("").validate().unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_https_url() {
+        target_with_url("https://github.com/gitbutlerapp/gitbutler.git")
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_scp_url() {
+        target_with_url("git@github.com:gitbutlerapp/gitbutler.git")
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_remote_name() {
+        Target::validate_remote_name("origin").unwrap();
+        Target::validate_remote_name("remote name").unwrap();
+        for remote_name in ["", "my/remote", "remote\nname"] {
+            assert!(matches!(
+                Target::validate_remote_name(remote_name),
+                Err(TargetError::InvalidRemoteName(name)) if name == remote_name
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_push_remote_name() {
+        let target = Target {
+            push_remote_name: Some("fork/name".to_string()),
+            push_remote_url: Some("https://github.com/fork/gitbutler.git".to_string()),
+            ..target_with_url("https://github.com/gitbutlerapp/gitbutler.git")
+        };
+        assert!(matches!(
+            TargetError::from(target.validate().unwrap_err()),
+            TargetError::InvalidRemoteName(name) if name == "fork/name"
+        ));
+    }
+
+    #[test]
+    fn test_sort() {
+        let target = |remote: &str, branch: &str, sha: &str| Target {
+            branch: git::RemoteRefname::new(remote, branch),
+            sha: sha.parse().unwrap(),
+            ..target_with_url("remote url")
+        };
+        let low_sha = "0123456789abcdef0123456789abcdef01234567";
+        let high_sha = "fedcba9876543210fedcba9876543210fedcba98";
+
+        let mut targets = vec![
+            target("upstream", "main", low_sha),
+            target("origin", "main", high_sha),
+            target("origin", "develop", high_sha),
+            target("origin", "main", low_sha),
+        ];
+        targets.sort();
+        assert_eq!(
+            targets,
+            vec![
+                target("origin", "develop", high_sha),
+                target("origin", "main", low_sha),
+                target("origin", "main", high_sha),
+                target("upstream", "main", low_sha),
+            ]
+        );
+
+        // only equal targets compare equal
+        let behind = Target {
+            behind: 1,
+            ..target("origin", "main", low_sha)
+        };
+        assert_ne!(
+            behind.cmp(&target("origin", "main", low_sha)),
+            std::cmp::Ordering::Equal
+        );
+        assert_eq!(
+            [behind.clone(), behind.clone()]
+                .into_iter()
+                .collect::<std::collections::HashSet<_>>()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_canonical_ref_short() {
+        let target = Target {
+            branch: git::RemoteRefname::new("origin", "main"),
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.canonical_ref(), "refs/remotes/origin/main");
+    }
+
+    #[test]
+    fn test_canonical_ref_fully_qualified() {
+        for (remote, branch) in [
+            ("origin", "refs/remotes/origin/main"),
+            ("refs/remotes/origin", "main"),
+            ("origin", "refs/heads/main"),
+        ] {
+            let target = Target {
+                branch: git::RemoteRefname::new(remote, branch),
+                ..target_with_url("remote url")
+            };
+            assert_eq!(target.canonical_ref(), "refs/remotes/origin/main");
+            assert_eq!(target.normalized().branch.remote(), "origin");
+            assert_eq!(target.normalized().branch.branch(), "main");
+        }
+    }
+
+    #[test]
+    fn test_compute_behind() {
+        let repository = test_utils::test_repository();
+        let base = repository.head().unwrap().peel_to_commit().unwrap().id();
+        for content in ["1", "2"] {
+            std::fs::write(
+                repository.path().parent().unwrap().join("file.txt"),
+                content,
+            )
+            .unwrap();
+            test_utils::commit_all(&repository);
+        }
+        let tip = repository.head().unwrap().peel_to_commit().unwrap().id();
+        repository
+            .reference(
+                &"refs/remotes/origin/master".parse().unwrap(),
+                tip,
+                true,
+                "",
+            )
+            .unwrap();
+
+        let target = Target {
+            sha: base,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.compute_behind(&repository).unwrap(), 2);
+
+        let target = Target {
+            sha: tip,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.compute_behind(&repository).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compute_ahead() {
+        let repository = test_utils::test_repository();
+        let base = repository.head().unwrap().peel_to_commit().unwrap().id();
+        for content in ["1", "2"] {
+            std::fs::write(
+                repository.path().parent().unwrap().join("file.txt"),
+                content,
+            )
+            .unwrap();
+            test_utils::commit_all(&repository);
+        }
+        let head = repository.head().unwrap().peel_to_commit().unwrap().id();
+
+        let target = Target {
+            sha: base,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.compute_ahead(&repository, head).unwrap(), 2);
+        assert_eq!(target.compute_ahead(&repository, base).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_from_upstream() {
+        let repository = test_utils::test_repository();
+        let head = repository.head().unwrap().peel_to_commit().unwrap().id();
+        let remote_repository = test_utils::empty_bare_repository();
+        let url: git::Url = format!("file://{}", remote_repository.path().display())
+            .parse()
+            .unwrap();
+        repository.remote("origin", &url).unwrap();
+        repository
+            .reference(
+                &"refs/remotes/origin/master".parse().unwrap(),
+                head,
+                true,
+                "",
+            )
+            .unwrap();
+
+        let target = Target::from_upstream(&repository, "origin", "master").unwrap();
+        assert_eq!(
+            target.branch,
+            "refs/remotes/origin/master"
+                .parse::<git::RemoteRefname>()
+                .unwrap()
+        );
+        assert_eq!(target.remote_url, url.to_string());
+        assert_eq!(target.sha, head);
+        assert_eq!(target.behind, 0);
+
+        assert!(matches!(
+            Target::from_upstream(&repository, "unknown", "master"),
+            Err(TargetError::UnknownRemote(remote)) if remote == "unknown"
+        ));
+        assert!(matches!(
+            Target::from_upstream(&repository, "origin", "missing"),
+            Err(TargetError::NoSuchUpstream { remote, branch })
+                if remote == "origin" && branch == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_compute_behind_tag() {
+        let repository = test_utils::test_repository();
+        let base = repository.head().unwrap().peel_to_commit().unwrap().id();
+        std::fs::write(repository.path().parent().unwrap().join("file.txt"), "1").unwrap();
+        let tip = test_utils::commit_all(&repository);
+        // the remote branch moves on, but a tag target doesn't follow it
+        repository
+            .reference(
+                &"refs/remotes/origin/master".parse().unwrap(),
+                tip,
+                true,
+                "",
+            )
+            .unwrap();
+        repository
+            .reference(&"refs/tags/v1".parse().unwrap(), base, true, "")
+            .unwrap();
+
+        let target = Target {
+            branch: "refs/remotes/origin/v1".parse().unwrap(),
+            sha: base,
+            ref_kind: TargetRefKind::Tag,
+            ahead: None,
+            note: None,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.target_ref(), TargetRef::Tag("v1".to_string()));
+        assert_eq!(target.canonical_ref(), "refs/tags/v1");
+        assert_eq!(target.compute_behind(&repository).unwrap(), 0);
+
+        // unless the tag itself is moved
+        repository
+            .reference(&"refs/tags/v1".parse().unwrap(), tip, true, "")
+            .unwrap();
+        assert_eq!(target.compute_behind(&repository).unwrap(), 1);
+
+        let target = Target {
+            sha: base,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.target_ref(), TargetRef::Branch("master".to_string()));
+        assert_eq!(target.compute_behind(&repository).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let repository = test_utils::test_repository();
+        let head = repository.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let signature = git::Signature::now("test", "test@email.com").unwrap();
+
+        let unsigned = Target {
+            sha: head.id(),
+            ..target_with_url("remote url")
+        };
+        assert_eq!(unsigned.verify_signature(&repository).unwrap(), Some(false));
+
+        let signed = Target {
+            sha: repository
+                .commit_signed(
+                    &signature,
+                    "signed",
+                    &tree,
+                    &[&head],
+                    &crate::keys::PrivateKey::generate(),
+                )
+                .unwrap(),
+            ..target_with_url("remote url")
+        };
+        assert_eq!(signed.verify_signature(&repository).unwrap(), Some(true));
+
+        // gpg signatures can't be checked
+        let git2_repository = git2::Repository::open(repository.path()).unwrap();
+        let git2_head = git2_repository.find_commit(head.id().into()).unwrap();
+        let git2_signature = git2::Signature::now("test", "test@email.com").unwrap();
+        let buffer = git2_repository
+            .commit_create_buffer(
+                &git2_signature,
+                &git2_signature,
+                "gpg signed",
+                &git2_head.tree().unwrap(),
+                &[&git2_head],
+            )
+            .unwrap();
+        let gpg_signed = Target {
+            sha: git2_repository
+                .commit_signed(
+                    std::str::from_utf8(&buffer).unwrap(),
+                    "-----BEGIN PGP SIGNATURE-----\n\nnot checked\n-----END PGP SIGNATURE-----",
+                    None,
+                )
+                .unwrap()
+                .into(),
+            ..target_with_url("remote url")
+        };
+        assert_eq!(gpg_signed.verify_signature(&repository).unwrap(), None);
+    }
+
+    #[test]
+    fn test_compute_behind_fetched() {
+        let repository = test_utils::test_repository();
+        let base = repository.head().unwrap().peel_to_commit().unwrap().id();
+        for content in ["1", "2"] {
+            std::fs::write(
+                repository.path().parent().unwrap().join("file.txt"),
+                content,
+            )
+            .unwrap();
+            test_utils::commit_all(&repository);
+        }
+        let fetched = repository.head().unwrap().peel_to_commit().unwrap().id();
+
+        // the remote branch doesn't matter, only the fetched tip does
+        let target = Target {
+            sha: base,
+            last_fetched_sha: Some(fetched),
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.compute_behind_fetched(&repository).unwrap(), 2);
+
+        let target = Target {
+            sha: base,
+            last_fetched_sha: None,
+            ..target_with_url("remote url")
+        };
+        assert_eq!(target.compute_behind_fetched(&repository).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_diff_same() {
+        let target = target_with_url("remote url");
+        assert_eq!(target.diff(&target), vec![]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let target = target_with_url("remote url");
+        let other = Target {
+            branch: "refs/remotes/fork/master".parse().unwrap(),
+            behind: target.behind + 1,
+            ..target.clone()
+        };
+        assert_eq!(
+            target.diff(&other),
+            vec![
+                TargetFieldDiff {
+                    field: "branch_name",
+                    old: format!("{}/{}", target.branch.remote(), target.branch.branch()),
+                    new: "fork/master".to_string(),
+                },
+                TargetFieldDiff {
+                    field: "remote_name",
+                    old: target.branch.remote().to_string(),
+                    new: "fork".to_string(),
+                },
+                TargetFieldDiff {
+                    field: "behind",
+                    old: target.behind.to_string(),
+                    new: other.behind.to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_sha() {
+        let repository = test_utils::test_repository();
+        let head = repository.head().unwrap().peel_to_commit().unwrap().id();
+        let full = head.to_string();
+
+        assert_eq!(Target::resolve_sha(&repository, &full).unwrap(), head);
+        let abbreviated = full.chars().take(7).collect::<String>();
+        assert_eq!(
+            Target::resolve_sha(&repository, &abbreviated).unwrap(),
+            head
+        );
+
+        let unknown = if full.starts_with('0') {
+            "1111111"
+        } else {
+            "0000000"
+        };
+        Target::resolve_sha(&repository, unknown).unwrap_err();
+    }
+
+    #[test]
+    fn test_compute_behind_diverged() {
+        let repository = test_utils::test_repository();
+        let base = repository.head().unwrap().peel_to_commit().unwrap().id();
+        std::fs::write(repository.path().parent().unwrap().join("file.txt"), "1").unwrap();
+        let local = test_utils::commit_all(&repository);
+        // the remote was force pushed back to the base commit
+        repository
+            .reference(
+                &"refs/remotes/origin/master".parse().unwrap(),
+                base,
+                true,
+                "",
+            )
+            .unwrap();
+
+        let target = Target {
+            sha: local,
+            ..target_with_url("remote url")
+        };
+        assert!(matches!(
+            target.compute_behind(&repository),
+            Err(BehindError::Diverged { .. })
+        ));
+    }
+}
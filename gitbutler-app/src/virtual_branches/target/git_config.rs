@@ -0,0 +1,322 @@
+use anyhow::{Context, Result};
+
+use crate::{git, project_repository, reader, virtual_branches::BranchId};
+
+use super::{with_branch_id, Target, TargetError, FIELD_FILES};
+
+// the default target is stored as `[gitbutler "target"]`, and the target of a branch as
+// `[gitbutler "target.<branch id>"]`.
+const SECTION: &str = "gitbutler.target";
+
+/// Reads and writes targets in the repository's own git config rather than in the gitbutler
+/// data, so that they travel with the repository. Each field is a key of its own, named like
+/// the file the field is stored in, with dashes for underscores.
+///
+/// All keys of a write are applied to a copy of the config taken under git's lock of the config
+/// file, which then replaces the config at once, so that neither git nor a concurrent reader
+/// sees a partly written target. Writing fails rather than waits while git holds the lock.
+pub struct GitConfigTargetWriter<'writer> {
+    repository: &'writer git::Repository,
+}
+
+impl<'writer> GitConfigTargetWriter<'writer> {
+    pub fn new(repository: &'writer git::Repository) -> Self {
+        Self { repository }
+    }
+
+    /// The writer for the project's repository, if its project is set to store targets in the
+    /// git config.
+    pub fn for_project(
+        project_repository: &'writer project_repository::Repository,
+    ) -> Option<Self> {
+        project_repository
+            .project()
+            .store_targets_in_git_config
+            .unwrap_or(false)
+            .then(|| Self::new(&project_repository.git_repository))
+    }
+
+    pub fn write_default(&self, target: &Target) -> Result<(), TargetError> {
+        self.write_at(SECTION, target)
+    }
+
+    pub fn write(&self, id: &BranchId, target: &Target) -> Result<(), TargetError> {
+        self.write_at(&branch_section(id), target)
+            .map_err(|error| match error {
+                TargetError::MalformedSha {
+                    branch_id: None,
+                    value,
+                } => TargetError::MalformedSha {
+                    branch_id: Some(*id),
+                    value,
+                },
+                error => error,
+            })
+    }
+
+    pub fn read_default(&self) -> Result<Target, reader::Error> {
+        self.read_at(SECTION)
+    }
+
+    /// Reads the branch's own target, falling back to the default target if it has none.
+    pub fn read(&self, id: &BranchId) -> Result<Target, reader::Error> {
+        match self.read_at(&branch_section(id)) {
+            Err(reader::Error::NotFound) => self.read_default(),
+            result => result.map_err(|error| with_branch_id(error, id)),
+        }
+    }
+
+    pub fn remove(&self, id: &BranchId) -> Result<()> {
+        let section = branch_section(id);
+        self.transaction(|config| {
+            for field in FIELD_FILES {
+                let key = key(&section, field);
+                config
+                    .remove(&key)
+                    .with_context(|| format!("failed to remove {}", key))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn config(&self) -> Result<git::Config> {
+        self.repository
+            .local_config()
+            .context("failed to open git config")
+    }
+
+    // applies `update` to a copy of the local config in `config.lock`, which git takes as the
+    // lock of the config file, and renames the copy over the config once all of it succeeded.
+    fn transaction(&self, update: impl FnOnce(&mut git::Config) -> Result<()>) -> Result<()> {
+        let path = self.repository.path().join("config");
+        let lock_path = path.with_extension("lock");
+        let lock = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .with_context(|| format!("failed to lock {}", path.display()))?;
+
+        let result = update_copy(&path, &lock, &lock_path, update).and_then(|()| {
+            std::fs::rename(&lock_path, &path).context("failed to replace git config")
+        });
+        if result.is_err() {
+            // releases the lock, leaving the config as it was
+            let _ = std::fs::remove_file(&lock_path);
+        }
+        result
+    }
+
+    fn write_at(&self, section: &str, target: &Target) -> Result<(), TargetError> {
+        target.validate()?;
+        let target = target.normalized();
+        self.transaction(|config| {
+            // fields the files layout keeps for older readers only, like `remote_name`, are
+            // left out
+            for (field, value) in target.fields() {
+                if !FIELD_FILES.contains(&field) {
+                    continue;
+                }
+                let key = key(section, field);
+                match value {
+                    Some(value) => config.set_str(&key, &value),
+                    None => config.remove(&key),
+                }
+                .with_context(|| format!("failed to write {}", key))?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn read_at(&self, section: &str) -> Result<Target, reader::Error> {
+        let config = self.config().map_err(config_error)?;
+        let results = FIELD_FILES
+            .iter()
+            .map(|field| match config.get_string(&key(section, field)) {
+                Ok(Some(value)) => Ok(reader::Content::UTF8(value)),
+                Ok(None) => Err(reader::Error::NotFound),
+                Err(error) => Err(config_error(error.into())),
+            })
+            .collect::<Vec<_>>();
+        if results
+            .iter()
+            .all(|result| matches!(result, Err(reader::Error::NotFound)))
+        {
+            return Err(reader::Error::NotFound);
+        }
+        Target::from_field_files(None, &results)
+    }
+}
+
+// copies the config at `path` into the lock file, and applies `update` to the copy.
+fn update_copy(
+    path: &std::path::Path,
+    mut lock: &std::fs::File,
+    lock_path: &std::path::Path,
+    update: impl FnOnce(&mut git::Config) -> Result<()>,
+) -> Result<()> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(error) => return Err(error).context("failed to read git config"),
+    };
+    std::io::Write::write_all(&mut lock, &contents)
+        .and_then(|()| lock.sync_all())
+        .context("failed to copy git config")?;
+    let mut config = git::Config::open(lock_path).context("failed to open git config")?;
+    update(&mut config)
+}
+
+fn branch_section(id: &BranchId) -> String {
+    format!("{}.{}", SECTION, id)
+}
+
+fn key(section: &str, field: &str) -> String {
+    format!("{}.{}", section, field.replace('_', "-"))
+}
+
+fn config_error(error: anyhow::Error) -> reader::Error {
+    reader::Error::Io(
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("git config: {:#}", error),
+        )
+        .into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{projects, test_utils};
+
+    use super::*;
+
+    fn test_target() -> Target {
+        Target {
+            remote_url: "https://github.com/gitbutlerapp/gitbutler.git".to_string(),
+            behind: 2,
+            push_remote_name: Some("fork".to_string()),
+            push_remote_url: Some("https://github.com/fork/gitbutler.git".to_string()),
+            sha_verified: Some(true),
+            note: Some("release base".to_string()),
+            ..test_utils::test_target()
+        }
+    }
+
+    #[test]
+    fn test_write_read() -> Result<()> {
+        let repository = test_utils::test_repository();
+        let writer = GitConfigTargetWriter::new(&repository);
+        let id = BranchId::generate();
+
+        assert!(matches!(
+            writer.read_default(),
+            Err(reader::Error::NotFound)
+        ));
+
+        let default_target = test_target();
+        writer.write_default(&default_target)?;
+        assert_eq!(writer.read_default()?, default_target);
+        // branches without a target of their own read the default one
+        assert_eq!(writer.read(&id)?, default_target);
+
+        let branch_target = Target {
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            push_remote_name: None,
+            push_remote_url: None,
+            note: None,
+            ..test_target()
+        };
+        writer.write(&id, &branch_target)?;
+        assert_eq!(writer.read(&id)?, branch_target);
+        assert_eq!(writer.read_default()?, default_target);
+
+        writer.remove(&id)?;
+        assert_eq!(writer.read(&id)?, default_target);
+        writer.remove(&id)?;
+
+        // fields that are no longer set are removed
+        writer.write_default(&branch_target)?;
+        assert_eq!(writer.read_default()?, branch_target);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_section_layout() -> Result<()> {
+        let repository = test_utils::test_repository();
+        let writer = GitConfigTargetWriter::new(&repository);
+        let id = BranchId::generate();
+        writer.write_default(&test_target())?;
+        writer.write(&id, &test_target())?;
+
+        let config = std::fs::read_to_string(repository.path().join("config"))?;
+        let section = |name: &str| {
+            let start = config.find(&format!("[gitbutler \"{}\"]", name)).unwrap();
+            let lines = config.get(start..).unwrap().lines().skip(1);
+            lines
+                .take_while(|line| !line.starts_with('['))
+                .map(str::trim)
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            "branch-name = origin/master",
+            "remote-url = https://github.com/gitbutlerapp/gitbutler.git",
+            "sha = 0123456789abcdef0123456789abcdef01234567",
+            "behind = 2",
+            "push-remote-name = fork",
+            "push-remote-url = https://github.com/fork/gitbutler.git",
+            "sha-verified = true",
+            "note = release base",
+        ];
+        assert_eq!(section("target"), expected);
+        assert_eq!(section(&format!("target.{}", id)), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_is_atomic() -> Result<()> {
+        let repository = test_utils::test_repository();
+        let writer = GitConfigTargetWriter::new(&repository);
+        writer.write_default(&test_target())?;
+        let config_path = repository.path().join("config");
+        let config = std::fs::read_to_string(&config_path)?;
+
+        // git holds the lock, so nothing is written
+        let lock_path = repository.path().join("config.lock");
+        std::fs::write(&lock_path, "")?;
+        let updated = Target {
+            behind: 5,
+            note: None,
+            ..test_target()
+        };
+        writer.write_default(&updated).unwrap_err();
+        assert_eq!(std::fs::read_to_string(&config_path)?, config);
+        // and the lock is still git's
+        assert!(lock_path.exists());
+        std::fs::remove_file(&lock_path)?;
+
+        writer.write_default(&updated)?;
+        assert_eq!(writer.read_default()?, updated);
+        assert!(!lock_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_for_project() -> Result<()> {
+        let suite = test_utils::Suite::default();
+        let case = suite.new_case();
+        assert!(GitConfigTargetWriter::for_project(&case.project_repository).is_none());
+
+        let project = projects::Project {
+            store_targets_in_git_config: Some(true),
+            ..case.project.clone()
+        };
+        let project_repository = project_repository::Repository::open(&project)?;
+        assert!(GitConfigTargetWriter::for_project(&project_repository).is_some());
+
+        Ok(())
+    }
+}
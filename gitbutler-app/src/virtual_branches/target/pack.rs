@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::{reader, virtual_branches::BranchId};
+
+use super::Target;
+
+/// Where packed targets are stored, relative to the gitbutler data directory.
+pub const PACK_PATH: &str = "branches/targets.pack";
+
+// recorded in place of the json when a branch's target is removed.
+const TOMBSTONE: &str = "-";
+
+/// The targets of all branches in a single append-only file, so that projects with
+/// thousands of branches don't need a directory of files per target.
+///
+/// Each write appends a `<branch id> <target json>` line, and the last line of a branch
+/// wins. The index of the latest line of every branch is built when the pack is opened.
+#[derive(Default)]
+pub struct Pack {
+    // bytes of the file covered by the index, to tell whether it was appended to since.
+    len: usize,
+    // latest target json of each branch, or `None` if the target was removed.
+    index: BTreeMap<BranchId, Option<String>>,
+}
+
+impl Pack {
+    /// Opens the pack, which is empty if it doesn't exist yet.
+    pub fn open(reader: &reader::Reader) -> Result<Self, reader::Error> {
+        Self::from_read(reader.read(PACK_PATH))
+    }
+
+    /// Builds the pack from the result of reading its file, which is empty if it doesn't
+    /// exist yet.
+    pub fn from_read(read: Result<reader::Content, reader::Error>) -> Result<Self, reader::Error> {
+        let content: String = match read {
+            Ok(content) => content.try_into()?,
+            Err(reader::Error::NotFound) => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        let mut pack = Self::default();
+        // a line without a newline is the remainder of an interrupted append.
+        for line in content.split_inclusive('\n').filter(|l| l.ends_with('\n')) {
+            pack.insert(line)?;
+        }
+        Ok(pack)
+    }
+
+    /// The line recording that the branch's target is now `target`, or that it was removed.
+    pub fn record(id: &BranchId, target: Option<&Target>) -> Result<String, serde_json::Error> {
+        let json = match target {
+            Some(target) => target.to_json()?,
+            None => TOMBSTONE.to_string(),
+        };
+        Ok(format!("{} {}\n", id, json))
+    }
+
+    /// Adds a line that was appended to the pack to the index.
+    pub fn insert(&mut self, line: &str) -> Result<(), reader::Error> {
+        let (id, json) = line
+            .trim_end_matches('\n')
+            .split_once(' ')
+            .and_then(|(id, json)| Some((id.parse::<BranchId>().ok()?, json)))
+            .ok_or_else(|| {
+                reader::Error::Io(
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{}: malformed line {:?}", PACK_PATH, line),
+                    )
+                    .into(),
+                )
+            })?;
+        self.index
+            .insert(id, (json != TOMBSTONE).then(|| json.to_string()));
+        self.len += line.len();
+        Ok(())
+    }
+
+    /// The branch's target, if the pack holds one.
+    pub fn get(&self, id: &BranchId) -> Option<Result<Target, reader::Error>> {
+        let json = self.index.get(id)?.as_ref()?;
+        Some(Target::from_json(&reader::Content::UTF8(json.clone())))
+    }
+
+    /// The latest line of the branch, which appending again undoes the lines after it.
+    pub fn line(&self, id: &BranchId) -> Option<String> {
+        let json = self.index.get(id)?;
+        Some(format!("{} {}\n", id, json.as_deref().unwrap_or(TOMBSTONE)))
+    }
+
+    /// The branches the pack has a line for, including those whose target was removed.
+    pub fn ids(&self) -> impl Iterator<Item = &BranchId> {
+        self.index.keys()
+    }
+
+    /// How many bytes of the file the index covers.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::test_target;
+
+    use super::*;
+
+    fn target(behind: usize) -> Target {
+        Target {
+            behind,
+            ..test_target()
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let id = BranchId::generate();
+        let other_id = BranchId::generate();
+
+        let mut pack = Pack::default();
+        for line in [
+            Pack::record(&id, Some(&target(1))).unwrap(),
+            Pack::record(&other_id, Some(&target(2))).unwrap(),
+            Pack::record(&id, Some(&target(3))).unwrap(),
+        ] {
+            pack.insert(&line).unwrap();
+        }
+        assert_eq!(pack.get(&id).unwrap().unwrap(), target(3));
+        assert_eq!(pack.get(&other_id).unwrap().unwrap(), target(2));
+
+        pack.insert(&Pack::record(&id, None).unwrap()).unwrap();
+        assert!(pack.get(&id).is_none());
+        assert!(pack.get(&BranchId::generate()).is_none());
+    }
+
+    #[test]
+    fn test_insert_malformed() {
+        let mut pack = Pack::default();
+        pack.insert("not an id {}\n").unwrap_err();
+        pack.insert("no separator\n").unwrap_err();
+        assert_eq!(pack.size(), 0);
+    }
+}
@@ -0,0 +1,845 @@
+use std::{
+    collections::BTreeMap,
+    time::{self, Duration},
+};
+
+use crate::{reader, sessions, virtual_branches::BranchId};
+
+use super::{
+    checksum,
+    encryption::EncryptionKey,
+    field_size_limit,
+    pack::{Pack, PACK_PATH},
+    remote_default_dir, with_branch_id, Target, CHECKED_TIMESTAMP_FILE, FETCHED_TIMESTAMP_FILE,
+    FIELD_FILES, TIMESTAMP_FILES,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("{0}: checksum mismatch")]
+    Corruption(String),
+    #[error(transparent)]
+    Reader(#[from] reader::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// When a branch's target was first written and when it last changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetTimestamps {
+    pub created_timestamp_ms: u128,
+    pub updated_timestamp_ms: u128,
+}
+
+/// The remote and branch names of a target exactly as stored, which may not be valid UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawTargetNames {
+    pub remote_name: Vec<u8>,
+    pub branch_name: Vec<u8>,
+}
+
+impl RawTargetNames {
+    pub fn remote_name_lossy(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.remote_name)
+    }
+
+    pub fn branch_name_lossy(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.branch_name)
+    }
+}
+
+pub struct TargetReader<'r> {
+    reader: &'r reader::Reader<'r>,
+    // opened on first use, as most projects don't have one.
+    pack: once_cell::unsync::OnceCell<Pack>,
+    encryption: Option<EncryptionKey>,
+}
+
+impl<'r> TargetReader<'r> {
+    pub fn new(reader: &'r sessions::Reader<'r>) -> Self {
+        Self {
+            reader: reader.reader(),
+            pack: once_cell::unsync::OnceCell::new(),
+            encryption: reader.target_encryption_key().cloned(),
+        }
+    }
+
+    /// Reads targets straight from `reader`, e.g. one opened on the gitbutler data directory,
+    /// rather than from a session.
+    pub fn from_reader(reader: &'r reader::Reader<'r>) -> Self {
+        Self {
+            reader,
+            pack: once_cell::unsync::OnceCell::new(),
+            encryption: None,
+        }
+    }
+
+    /// Decrypts the urls of targets written with `Writer::with_encryption` using `key`. Readers
+    /// of sessions already use the key of the session's repository, if it has one.
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    pub fn read_default(&self) -> Result<Target, reader::Error> {
+        self.opened(Target::read_from(self.reader, "branches")?)
+    }
+
+    /// Reads the default target of the given remote, as written by
+    /// `Writer::write_default_for_remote`.
+    pub fn read_default_for_remote(&self, remote_name: &str) -> Result<Target, reader::Error> {
+        self.opened(Target::read_from(
+            self.reader,
+            &remote_default_dir(remote_name)?,
+        )?)
+    }
+
+    /// Reads the default targets of all remotes that have one, keyed by remote name. The
+    /// primary default target is not included.
+    pub fn read_remote_defaults(&self) -> Result<BTreeMap<String, Target>, reader::Error> {
+        let remote_names = self
+            .reader
+            .list_files("branches/remotes")
+            .map_err(|e| {
+                reader::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e).into())
+            })?
+            .into_iter()
+            .filter_map(|path| {
+                path.components()
+                    .next()
+                    .map(|name| name.as_os_str().to_string_lossy().to_string())
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+
+        remote_names
+            .into_iter()
+            .map(|remote_name| {
+                let target = self.read_default_for_remote(&remote_name)?;
+                Ok((remote_name, target))
+            })
+            .collect()
+    }
+
+    /// Compares the field files of the branch's target against the checksum written along
+    /// with them. Targets written without a checksum are assumed intact.
+    pub fn verify_integrity(&self, id: &BranchId) -> Result<(), IntegrityError> {
+        let dir = format!("branches/{}/target", id);
+        let reader = self.reader.sub(&dir);
+
+        let expected: String = match reader.read(".checksum") {
+            Ok(content) => content.try_into().map_err(reader::Error::from)?,
+            Err(reader::Error::NotFound) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let files = reader
+            .list_files("")?
+            .into_iter()
+            // bookkeeping files such as `.checksum`, `.history` and the timestamps are not
+            // fields
+            .filter(|name| {
+                let name = name.to_string_lossy();
+                !name.starts_with('.')
+                    && !TIMESTAMP_FILES.contains(&name.as_ref())
+                    && name != FETCHED_TIMESTAMP_FILE
+            })
+            .map(|name| {
+                let contents: String = reader.read(&name)?.try_into()?;
+                Ok((name.display().to_string(), contents))
+            })
+            .collect::<Result<Vec<_>, reader::Error>>()?;
+
+        if checksum(&files) == expected {
+            Ok(())
+        } else {
+            Err(IntegrityError::Corruption(dir))
+        }
+    }
+
+    /// Reads when the branch's target was created and last updated. Only targets in the one
+    /// file per field layout keep timestamps.
+    pub fn read_timestamps(&self, id: &BranchId) -> Result<TargetTimestamps, reader::Error> {
+        let reader = self.reader.sub(format!("branches/{}/target", id));
+        Ok(TargetTimestamps {
+            created_timestamp_ms: reader.read("created_timestamp_ms")?.try_into()?,
+            updated_timestamp_ms: reader.read("updated_timestamp_ms")?.try_into()?,
+        })
+    }
+
+    /// Reads when the branch's target was last fetched, as recorded by `Writer::mark_fetched`,
+    /// or `None` if it never was.
+    pub fn read_last_fetched(&self, id: &BranchId) -> Result<Option<u128>, reader::Error> {
+        match self
+            .reader
+            .read(format!("branches/{}/target/{}", id, FETCHED_TIMESTAMP_FILE))
+        {
+            Ok(content) => Ok(Some(content.try_into()?)),
+            Err(reader::Error::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads when the branch's target was last checked, as recorded by `Writer::touch`, or
+    /// `None` if it never was.
+    pub fn read_last_checked(&self, id: &BranchId) -> Result<Option<u128>, reader::Error> {
+        match self
+            .reader
+            .read(format!("branches/{}/target/{}", id, CHECKED_TIMESTAMP_FILE))
+        {
+            Ok(content) => Ok(Some(content.try_into()?)),
+            Err(reader::Error::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Whether the branch's target is due to be fetched, because it was last fetched more
+    /// than `max_age` ago, or never.
+    pub fn is_stale(&self, id: &BranchId, max_age: Duration) -> Result<bool, reader::Error> {
+        let Some(last_fetched_ms) = self.read_last_fetched(id)? else {
+            return Ok(true);
+        };
+        let now_ms = time::UNIX_EPOCH
+            .elapsed()
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?
+            .as_millis();
+        Ok(now_ms.saturating_sub(last_fetched_ms) > max_age.as_millis())
+    }
+
+    /// Reads the remote and branch names of the branch's own target byte for byte, such as
+    /// those written by `Writer::write_raw`, which `read` only returns lossily. Only targets in
+    /// the one file per field layout are supported.
+    pub fn read_raw_names(&self, id: &BranchId) -> Result<RawTargetNames, reader::Error> {
+        let reader = self.reader.sub(format!("branches/{}/target", id));
+        let remote_name = reader.read_bytes("remote_name")?;
+        // stored as `<remote>/<branch>`
+        let branch_name = reader.read_bytes("branch_name")?;
+        let branch_name = branch_name
+            .strip_prefix(remote_name.as_slice())
+            .and_then(|branch_name| branch_name.strip_prefix(b"/"))
+            .map_or(branch_name.clone(), <[u8]>::to_vec);
+        Ok(RawTargetNames {
+            remote_name,
+            branch_name,
+        })
+    }
+
+    /// Like `read`, but reads a consistent snapshot, even while targets are being written.
+    ///
+    /// All files the branch's target, and the default target, may be stored in are read in a
+    /// single batch, holding the directory lock only for as long as that takes. Writers apply
+    /// each write as a single batch under the same lock, so the target returned is the one
+    /// written by some write, never a mix of the fields of two writes. Neither the repository
+    /// lock nor the session is involved, so reading doesn't wait for writers to mark their
+    /// session active, and doesn't create a session.
+    pub fn read_snapshot(&self, id: &BranchId) -> Result<Target, reader::Error> {
+        self.opened(self.read_snapshot_stored(id)?)
+    }
+
+    // the target `read_snapshot` returns, as it is stored.
+    fn read_snapshot_stored(&self, id: &BranchId) -> Result<Target, reader::Error> {
+        let dir = format!("branches/{}", id);
+        let mut paths = vec![PACK_PATH.to_string()];
+        for dir in [dir.as_str(), "branches"] {
+            paths.push(format!("{}/target.json", dir));
+            for fields_dir in ["target", "target.old"] {
+                paths.extend(
+                    FIELD_FILES
+                        .iter()
+                        .map(|file| format!("{}/{}/{}", dir, fields_dir, file)),
+                );
+            }
+        }
+        let results = self.reader.batch_limited(&paths, &field_size_limit)?;
+        let (pack, results) = results.split_at(1);
+        let (own, default) = results.split_at(2 * FIELD_FILES.len() + 1);
+
+        if let Some(target) = Pack::from_read(pack[0].clone())?.get(id) {
+            return target;
+        }
+        for (dir, results) in [(dir.as_str(), own), ("branches", default)] {
+            let (json, fields) = results.split_at(1);
+            match &json[0] {
+                Ok(content) => return Target::from_json(content),
+                Err(reader::Error::NotFound) => {}
+                Err(error) => return Err(error.clone()),
+            }
+            // falling back to the fields a write interrupted between its renames left behind
+            let (fields, old_fields) = fields.split_at(FIELD_FILES.len());
+            for (fields_dir, fields) in [("target", fields), ("target.old", old_fields)] {
+                if fields
+                    .iter()
+                    .any(|field| !matches!(field, Err(reader::Error::NotFound)))
+                {
+                    return Target::from_field_files(
+                        Some(&self.reader.sub(format!("{}/{}", dir, fields_dir))),
+                        fields,
+                    );
+                }
+            }
+        }
+        Err(reader::Error::NotFound)
+    }
+
+    /// Reads the branch's own target, falling back to the default target if it has none.
+    /// Targets in the pack take precedence over those stored in the branch's directory.
+    pub fn read(&self, id: &BranchId) -> Result<Target, reader::Error> {
+        let pack = self.pack.get_or_try_init(|| Pack::open(self.reader))?;
+        if let Some(target) = pack.get(id) {
+            return self.opened(target?);
+        }
+
+        let dir = format!("branches/{}", id);
+        let exists = self
+            .reader
+            .exists(format!("{}/target.json", dir))
+            .map_err(reader::Error::from)?
+            || self
+                .reader
+                .exists(format!("{}/target", dir))
+                .map_err(reader::Error::from)?
+            || self
+                .reader
+                .exists(format!("{}/target.old", dir))
+                .map_err(reader::Error::from)?;
+        if !exists {
+            return self.read_default();
+        }
+
+        let target = Target::read_from(self.reader, &dir).map_err(|e| with_branch_id(e, id))?;
+        self.opened(target)
+    }
+
+    // the target with its urls decrypted if there is a key.
+    fn opened(&self, target: Target) -> Result<Target, reader::Error> {
+        match &self.encryption {
+            Some(key) => key.open(target),
+            None => Ok(target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use anyhow::Result;
+
+    use crate::{
+        git, sessions,
+        test_utils::{test_branch, test_target, Case, Suite},
+        virtual_branches::{branch, target::writer::TargetWriter},
+    };
+
+    use crate::virtual_branches::target::TargetError;
+
+    use super::*;
+
+    #[test]
+    fn test_read_not_found() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+
+        let reader = TargetReader::new(&session_reader);
+        let result = reader.read(&BranchId::generate());
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().to_string(), "file not found");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_deprecated_format() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let writer = crate::writer::DirWriter::open(gb_repository.root())?;
+        writer
+            .write_string("branches/target/name", "origin/master")
+            .unwrap();
+        writer
+            .write_string(
+                "branches/target/remote",
+                "git@github.com:gitbutlerapp/gitbutler.git",
+            )
+            .unwrap();
+        writer
+            .write_string(
+                "branches/target/sha",
+                "dd945831869e9593448aa622fa4342bbfb84813d",
+            )
+            .unwrap();
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        let read = reader.read_default().unwrap();
+        assert_eq!(read.branch.branch(), "master");
+        assert_eq!(read.branch.remote(), "origin");
+        assert_eq!(read.remote_url, "git@github.com:gitbutlerapp/gitbutler.git");
+        assert_eq!(
+            read.sha.to_string(),
+            "dd945831869e9593448aa622fa4342bbfb84813d"
+        );
+        assert_eq!(read.push_remote_name, None);
+        assert_eq!(read.push_remote_url, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_override_target() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+
+        let target = Target {
+            branch: "refs/remotes/remote/branch".parse().unwrap(),
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            ..test_target()
+        };
+
+        let default_target = Target {
+            branch: "refs/remotes/default remote/default branch"
+                .parse()
+                .unwrap(),
+            remote_url: "default remote url".to_string(),
+            ..test_target()
+        };
+
+        let branch_writer = branch::Writer::new(&gb_repository)?;
+        branch_writer.write(&mut branch)?;
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+
+        let target_writer = TargetWriter::new(&gb_repository)?;
+        let reader = TargetReader::new(&session_reader);
+
+        target_writer.write_default(&default_target)?;
+        assert_eq!(default_target, reader.read(&branch.id)?);
+
+        target_writer.write(&branch.id, &target)?;
+        assert_eq!(target, reader.read(&branch.id)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_default() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let target = Target {
+            remote_url: "git@github.com:gitbutlerapp/gitbutler.git".to_string(),
+            behind: 2,
+            ..test_target()
+        };
+
+        TargetWriter::new(&gb_repository)?.write_default(&target)?;
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        assert_eq!(reader.read_default()?, target);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_remote_defaults() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let primary = Target {
+            remote_url: "git@github.com:gitbutlerapp/gitbutler.git".to_string(),
+            ..test_target()
+        };
+        let origin = Target {
+            branch: "refs/remotes/origin/main".parse().unwrap(),
+            ..primary.clone()
+        };
+        let fork = Target {
+            branch: "refs/remotes/fork/main".parse().unwrap(),
+            remote_url: "git@github.com:fork/gitbutler.git".to_string(),
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            behind: 1,
+            ..test_target()
+        };
+
+        let target_writer = TargetWriter::new(&gb_repository)?;
+        target_writer.write_default_for_remote("origin", &origin)?;
+        target_writer.write_default_for_remote("fork", &fork)?;
+        // writing the primary default must leave the remote defaults alone
+        target_writer.write_default(&primary)?;
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        assert_eq!(reader.read_default()?, primary);
+        assert_eq!(reader.read_default_for_remote("origin")?, origin);
+        assert_eq!(reader.read_default_for_remote("fork")?, fork);
+        assert_eq!(
+            reader.read_remote_defaults()?,
+            BTreeMap::from([("fork".to_string(), fork), ("origin".to_string(), origin)])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_malformed_sha() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let writer = crate::writer::DirWriter::open(gb_repository.root())?;
+        writer.write_string("branches/target/branch_name", "origin/master")?;
+        writer.write_string("branches/target/remote_url", "remote url")?;
+        writer.write_string("branches/target/sha", "not a sha")?;
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        let error = reader.read_default().unwrap_err();
+        assert!(error.to_string().contains("sha"));
+        assert!(matches!(
+            TargetError::from(error),
+            TargetError::MalformedSha { branch_id: None, value } if value == "not a sha"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_sha_length() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+
+        let writer = crate::writer::DirWriter::open(gb_repository.root())?;
+        let dir = format!("branches/{}/target", branch.id);
+        writer.write_string(&format!("{}/branch_name", dir), "origin/master")?;
+        writer.write_string(&format!("{}/remote_url", dir), "remote url")?;
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let read_sha = |sha: &str| -> Result<Result<git::Oid, TargetError>> {
+            writer.write_string(&format!("{}/sha", dir), sha)?;
+            let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+            Ok(TargetReader::new(&session_reader)
+                .read(&branch.id)
+                .map(|target| target.sha)
+                .map_err(TargetError::from))
+        };
+
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(read_sha(sha)?.unwrap(), sha.parse()?);
+        // uppercase shas are rejected
+        assert!(matches!(
+            read_sha(&sha.to_uppercase())?,
+            Err(TargetError::MalformedSha { .. })
+        ));
+
+        // truncated shas are not padded into a different sha
+        let error = read_sha("0123456")?.unwrap_err();
+        assert!(error.to_string().contains(&branch.id.to_string()));
+        assert!(matches!(
+            error,
+            TargetError::MalformedSha { branch_id: Some(id), value }
+                if id == branch.id && value == "0123456"
+        ));
+        assert!(matches!(
+            read_sha(&format!("{}0", sha))?,
+            Err(TargetError::MalformedSha { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_field_too_large() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+
+        let writer = crate::writer::DirWriter::open(gb_repository.root())?;
+        let dir = format!("branches/{}/target", branch.id);
+        writer.write_string(&format!("{}/remote_url", dir), "remote url")?;
+        writer.write_string(
+            &format!("{}/sha", dir),
+            &format!("{}\n", "0123456789abcdef".repeat(4)),
+        )?;
+        // sparse, so that reading it whole would take a while
+        std::fs::File::create(gb_repository.root().join(&dir).join("branch_name"))?
+            .set_len(1 << 32)?;
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let read = || {
+            TargetError::from(
+                TargetReader::new(&session_reader)
+                    .read(&branch.id)
+                    .unwrap_err(),
+            )
+        };
+
+        assert!(matches!(
+            read(),
+            TargetError::FieldTooLarge { field, max_size } if field == "branch_name" && max_size == 64 * 1024
+        ));
+
+        writer.write_string(&format!("{}/branch_name", dir), "origin/master")?;
+        assert!(matches!(
+            read(),
+            TargetError::FieldTooLarge { field, max_size } if field == "sha" && max_size == 64
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_malformed_branch_name() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let writer = crate::writer::DirWriter::open(gb_repository.root())?;
+        writer.write_string("branches/target/branch_name", "master")?;
+        writer.write_string("branches/target/remote_url", "remote url")?;
+        writer.write_string(
+            "branches/target/sha",
+            "0123456789abcdef0123456789abcdef01234567",
+        )?;
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        let error = reader.read_default().unwrap_err();
+        assert!(error.to_string().contains("branch_name"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_snapshot_while_writing() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            gb_repository,
+            project_repository,
+            ..
+        } = suite.new_case();
+
+        let mut branch = test_branch();
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+
+        let first = Target {
+            branch: "refs/remotes/remote/first".parse().unwrap(),
+            remote_url: "remote url a".to_string(),
+            behind: 1,
+            ..test_target()
+        };
+        let second = Target {
+            branch: "refs/remotes/remote/second".parse().unwrap(),
+            remote_url: "remote url b".to_string(),
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            behind: 2,
+            ..first.clone()
+        };
+        TargetWriter::new(&gb_repository)?.write(&branch.id, &first)?;
+
+        let writer_repository = crate::gb_repository::Repository::open(
+            &suite.local_app_data,
+            &project_repository,
+            None,
+        )?;
+        let done = std::sync::atomic::AtomicBool::new(false);
+        let dir_reader = reader::Reader::open(gb_repository.root())?;
+        let target_reader = TargetReader::from_reader(&dir_reader);
+
+        std::thread::scope(|scope| -> Result<()> {
+            let writer = scope.spawn({
+                let (first, second, done) = (&first, &second, &done);
+                move || -> Result<()> {
+                    let target_writer = TargetWriter::new(&writer_repository)?;
+                    for target in [second, first].into_iter().cycle().take(100) {
+                        target_writer.write(&branch.id, target)?;
+                    }
+                    done.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+
+            let mut reads = 0_usize;
+            while !done.load(Ordering::SeqCst) {
+                let target = target_reader.read_snapshot(&branch.id)?;
+                assert!(target == first || target == second);
+                reads += 1;
+            }
+            assert!(reads > 0);
+
+            writer.join().unwrap()
+        })?;
+
+        assert_eq!(target_reader.read_snapshot(&branch.id)?, first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_snapshot_default() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let target = Target {
+            branch: "refs/remotes/remote/branch".parse().unwrap(),
+            ..test_target()
+        };
+        let dir_reader = reader::Reader::open(gb_repository.root())?;
+        let target_reader = TargetReader::from_reader(&dir_reader);
+        let id = BranchId::generate();
+        assert!(matches!(
+            target_reader.read_snapshot(&id),
+            Err(reader::Error::NotFound)
+        ));
+
+        let target_writer = TargetWriter::new(&gb_repository)?;
+        target_writer.write_default(&target)?;
+        assert_eq!(target_reader.read_snapshot(&id)?, target);
+
+        let own = Target {
+            behind: 1,
+            ..target.clone()
+        };
+        target_writer.write_json(&id, &own)?;
+        assert_eq!(target_reader.read_snapshot(&id)?, own);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_stale() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+        let target = Target {
+            branch: "refs/remotes/remote/branch".parse().unwrap(),
+            ..test_target()
+        };
+        let target_writer = TargetWriter::new(&gb_repository)?.with_checksums(true);
+        assert!(matches!(
+            target_writer.mark_fetched(&branch.id),
+            Err(TargetError::SourceMissing(id)) if id == branch.id
+        ));
+        target_writer.write(&branch.id, &target)?;
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+        let max_age = Duration::from_secs(60 * 60);
+
+        // never fetched
+        assert_eq!(reader.read_last_fetched(&branch.id)?, None);
+        assert!(reader.is_stale(&branch.id, max_age)?);
+
+        // fresh
+        let history_len = target_writer.history(&branch.id)?.len();
+        target_writer.mark_fetched(&branch.id)?;
+        let last_fetched_ms = reader.read_last_fetched(&branch.id)?.unwrap();
+        assert!(!reader.is_stale(&branch.id, max_age)?);
+        reader.verify_integrity(&branch.id)?;
+        // recorded in the history like a write
+        assert_eq!(target_writer.history(&branch.id)?.len(), history_len + 1);
+
+        // kept when the target is written again
+        target_writer.write(
+            &branch.id,
+            &Target {
+                behind: 1,
+                ..target.clone()
+            },
+        )?;
+        assert_eq!(reader.read_last_fetched(&branch.id)?, Some(last_fetched_ms));
+
+        // stale
+        std::fs::write(
+            gb_repository
+                .root()
+                .join("branches")
+                .join(branch.id.to_string())
+                .join("target")
+                .join("last_fetched_ms"),
+            (last_fetched_ms - 2 * max_age.as_millis()).to_string(),
+        )?;
+        assert!(reader.is_stale(&branch.id, max_age)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_integrity() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        let target = Target {
+            branch: "refs/remotes/remote/branch".parse().unwrap(),
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            ..test_target()
+        };
+
+        let branch_writer = branch::Writer::new(&gb_repository)?;
+        branch_writer.write(&mut branch)?;
+
+        TargetWriter::new(&gb_repository)?
+            .with_checksums(true)
+            .write(&branch.id, &target)?;
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        reader.verify_integrity(&branch.id)?;
+
+        // truncate the sha
+        std::fs::write(
+            gb_repository
+                .root()
+                .join("branches")
+                .join(branch.id.to_string())
+                .join("target")
+                .join("sha"),
+            "fedcba98",
+        )?;
+
+        assert!(matches!(
+            reader.verify_integrity(&branch.id),
+            Err(IntegrityError::Corruption(dir)) if dir == format!("branches/{}/target", branch.id)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_integrity_without_checksum() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = test_branch();
+        let target = Target {
+            branch: "refs/remotes/remote/branch".parse().unwrap(),
+            sha: "fedcba9876543210fedcba9876543210fedcba98".parse().unwrap(),
+            ..test_target()
+        };
+
+        let branch_writer = branch::Writer::new(&gb_repository)?;
+        branch_writer.write(&mut branch)?;
+        TargetWriter::new(&gb_repository)?.write(&branch.id, &target)?;
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let reader = TargetReader::new(&session_reader);
+
+        reader.verify_integrity(&branch.id)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeSet,
+    path,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver},
+    Stream, StreamExt,
+};
+use notify::{RecommendedWatcher, Watcher};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, FileIdMap};
+
+use crate::{gb_repository, virtual_branches::BranchId};
+
+/// Rapid changes to the same target, such as the files of a single write, are reported once.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A stream of ids of branches whose target changed on disk, whether written by this process
+/// or by someone else. Watching stops when it's dropped.
+pub struct TargetWatcher {
+    _debouncer: Debouncer<RecommendedWatcher, FileIdMap>,
+    rx: UnboundedReceiver<Result<BranchId>>,
+}
+
+impl TargetWatcher {
+    /// Starts watching the targets of the repository's branches. The watch is in place when
+    /// this returns, so reading the targets afterwards can't miss a change.
+    pub fn new(repository: &gb_repository::Repository) -> Result<Self> {
+        let branches_path = repository.root().join("branches");
+        std::fs::create_dir_all(&branches_path)
+            .with_context(|| format!("failed to create {}", branches_path.display()))?;
+
+        let (tx, rx) = unbounded();
+        let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, {
+            let branches_path = branches_path.clone();
+            move |result: DebounceEventResult| {
+                let results = match result {
+                    Ok(events) => events
+                        .iter()
+                        .filter(|event| !matches!(event.kind, notify::EventKind::Access(_)))
+                        .flat_map(|event| event.paths.iter())
+                        .filter_map(|path| changed_branch_id(&branches_path, path))
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .map(Ok)
+                        .collect::<Vec<_>>(),
+                    Err(errors) => errors
+                        .into_iter()
+                        .map(|error| Err(anyhow!(error).context("target watcher error")))
+                        .collect(),
+                };
+                for result in results {
+                    if tx.unbounded_send(result).is_err() {
+                        // the watcher was dropped
+                        return;
+                    }
+                }
+            }
+        })
+        .context("failed to create debouncer")?;
+
+        debouncer
+            .watcher()
+            .watch(&branches_path, notify::RecursiveMode::Recursive)
+            .with_context(|| format!("failed to watch {}", branches_path.display()))?;
+
+        Ok(Self {
+            _debouncer: debouncer,
+            rx,
+        })
+    }
+}
+
+impl Stream for TargetWatcher {
+    type Item = Result<BranchId>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+// `branches/<id>/target/...` and `branches/<id>/target.json` belong to the branch's target.
+fn changed_branch_id(branches_path: &path::Path, path: &path::Path) -> Option<BranchId> {
+    let mut components = path.strip_prefix(branches_path).ok()?.components();
+    let id = components.next()?.as_os_str().to_str()?.parse().ok()?;
+    let name = components.next()?.as_os_str();
+    (name == "target" || name == "target.json").then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_utils::{test_target, Case, Suite},
+        virtual_branches::{branch, target},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_changed_branch_id() {
+        let branches_path = path::Path::new("/gb/branches");
+        let id = BranchId::generate();
+
+        for name in ["target/sha", "target", "target.json"] {
+            assert_eq!(
+                changed_branch_id(
+                    branches_path,
+                    &branches_path.join(id.to_string()).join(name)
+                ),
+                Some(id)
+            );
+        }
+        assert_eq!(
+            changed_branch_id(
+                branches_path,
+                &branches_path.join(id.to_string()).join("meta/name")
+            ),
+            None
+        );
+        assert_eq!(
+            changed_branch_id(branches_path, &branches_path.join("target/sha")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+
+        let mut branch = branch::Branch {
+            id: BranchId::generate(),
+            name: "branch".to_string(),
+            notes: String::new(),
+            applied: true,
+            upstream: None,
+            upstream_head: None,
+            created_timestamp_ms: 0,
+            updated_timestamp_ms: 0,
+            head: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            tree: "0123456789abcdef0123456789abcdef01234567".parse().unwrap(),
+            ownership: branch::Ownership::default(),
+            order: 0,
+            selected_for_changes: None,
+        };
+        branch::Writer::new(&gb_repository)?.write(&mut branch)?;
+
+        let mut watcher = target::Writer::watch(&gb_repository)?;
+
+        target::Writer::new(&gb_repository)?.write(&branch.id, &test_target())?;
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.next())
+            .await
+            .context("no change within timeout")?
+            .context("watcher stopped")??;
+        assert_eq!(changed, branch.id);
+
+        Ok(())
+    }
+}
@@ -39,6 +39,16 @@ pub fn set_test_target(
             branch: "refs/remotes/origin/master".parse().unwrap(),
             remote_url: remote_repo.path().to_str().unwrap().parse().unwrap(),
             sha: remote_repo.head().unwrap().target().unwrap(),
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: target::TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
         })
         .expect("failed to write target");
 
@@ -118,6 +128,62 @@ fn test_commit_on_branch_then_change_file_then_get_status() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_tracked_inactive() -> Result<()> {
+    let Case {
+        project_repository,
+        gb_repository,
+        ..
+    } = Suite::default().new_case();
+
+    set_test_target(&gb_repository, &project_repository)?;
+
+    let mut ids = vec![];
+    for _ in 0..4 {
+        ids.push(
+            create_virtual_branch(
+                &gb_repository,
+                &project_repository,
+                &BranchCreateRequest::default(),
+            )
+            .expect("failed to create virtual branch")
+            .id,
+        );
+    }
+    let [applied_id, unapplied_id, untracked_id, malformed_id] = ids[..] else {
+        unreachable!()
+    };
+
+    let current_session = gb_repository.get_or_create_current_session()?;
+    let current_session_reader = sessions::Reader::open(&gb_repository, &current_session)?;
+    let branch_reader = branch::Reader::new(&current_session_reader);
+    let branch_writer = branch::Writer::new(&gb_repository)?;
+    let target_writer = target::Writer::new(&gb_repository)?;
+    let default_target = target::Reader::new(&current_session_reader).read_default()?;
+    for id in [applied_id, unapplied_id, malformed_id] {
+        target_writer.write(&id, &default_target)?;
+    }
+    for id in [unapplied_id, untracked_id] {
+        branch_writer.write(&mut Branch {
+            applied: false,
+            ..branch_reader.read(&id)?
+        })?;
+    }
+    fs::write(
+        gb_repository
+            .root()
+            .join("branches")
+            .join(malformed_id.to_string())
+            .join("meta")
+            .join("applied"),
+        "maybe",
+    )?;
+
+    assert_eq!(tracked_inactive(&gb_repository)?, vec![unapplied_id]);
+
+    Ok(())
+}
+
 #[test]
 fn test_signed_commit() -> Result<()> {
     let suite = Suite::default();
@@ -1016,6 +1082,16 @@ fn test_merge_vbranch_upstream_clean() -> Result<()> {
         branch: "refs/remotes/origin/master".parse().unwrap(),
         remote_url: "origin".to_string(),
         sha: target_oid,
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
     })?;
 
     // add some uncommitted work
@@ -1145,6 +1221,16 @@ fn test_merge_vbranch_upstream_conflict() -> Result<()> {
         branch: "refs/remotes/origin/master".parse().unwrap(),
         remote_url: "origin".to_string(),
         sha: target_oid,
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
     })?;
 
     // add some uncommitted work
@@ -1714,6 +1800,16 @@ fn test_upstream_integrated_vbranch() -> Result<()> {
         branch: "refs/remotes/origin/master".parse().unwrap(),
         remote_url: "http://origin.com/project".to_string(),
         sha: base_commit,
+        behind: 0,
+        push_remote_name: None,
+        push_remote_url: None,
+        last_fetched_sha: None,
+        sha_verified: None,
+        ref_kind: target::TargetRefKind::Branch,
+        ahead: None,
+        note: None,
+        pr_url: None,
+        pr_number: None,
     })?;
     project_repository
         .git_repository
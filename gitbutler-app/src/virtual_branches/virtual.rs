@@ -171,6 +171,28 @@ pub fn get_default_target(
     }
 }
 
+/// Lists the branches that have a target of their own but aren't applied, ordered by id.
+/// Branches whose `applied` flag is missing or malformed are left out, as it's unknown
+/// whether they are applied.
+pub fn tracked_inactive(gb_repository: &gb_repository::Repository) -> Result<Vec<BranchId>> {
+    let targets = target::Writer::read_all(gb_repository).context("failed to read targets")?;
+    let reader = reader::Reader::open(gb_repository.root()).context("failed to open reader")?;
+    let mut ids = targets
+        .targets
+        .into_keys()
+        .chain(targets.errors.into_keys())
+        .filter(|id| {
+            let applied = reader
+                .read(format!("branches/{}/meta/applied", id))
+                .ok()
+                .and_then(|applied| bool::try_from(applied).ok());
+            applied == Some(false)
+        })
+        .collect::<Vec<_>>();
+    ids.sort();
+    Ok(ids)
+}
+
 pub fn apply_branch(
     gb_repository: &gb_repository::Repository,
     project_repository: &project_repository::Repository,
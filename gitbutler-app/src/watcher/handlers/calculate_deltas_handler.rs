@@ -227,6 +227,16 @@ mod test {
             )
             .parse()
             .unwrap(),
+            behind: 0,
+            push_remote_name: None,
+            push_remote_url: None,
+            last_fetched_sha: None,
+            sha_verified: None,
+            ref_kind: virtual_branches::target::TargetRefKind::Branch,
+            ahead: None,
+            note: None,
+            pr_url: None,
+            pr_number: None,
         }
     }
 
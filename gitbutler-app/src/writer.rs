@@ -1,12 +1,66 @@
 use anyhow::Result;
 
-use crate::lock;
+use crate::{lock, reader};
 
-pub struct DirWriter(lock::Dir);
+/// A storage backend that batches of write tasks can be applied to.
+pub trait Writer {
+    fn apply(&self, tasks: &[BatchTask<String, String>]) -> Result<(), std::io::Error>;
+
+    /// Like `apply`, but returns `false` without writing anything if the storage is locked.
+    fn try_apply(&self, tasks: &[BatchTask<String, String>]) -> Result<bool, std::io::Error>;
+}
+
+/// Clones share the directory and its lock, so a writer opened once can be reused cheaply,
+/// including by writers of other kinds, see `TargetWriter::from_dir_writer`.
+#[derive(Clone)]
+pub struct DirWriter {
+    dir: lock::Dir,
+    fsync: bool,
+}
 
 impl DirWriter {
+    /// Opens the directory, creating it if it doesn't exist, along with the lock file next to
+    /// it, which takes I/O. Clone the writer rather than opening the same root again.
     pub fn open<P: AsRef<std::path::Path>>(root: P) -> Result<Self, std::io::Error> {
-        lock::Dir::new(root).map(Self)
+        let dir = lock::Dir::new(root)?;
+        Ok(Self { dir, fsync: false })
+    }
+
+    /// A reader of the directory written to, sharing its lock, which takes no I/O.
+    pub fn reader<'reader>(&self) -> reader::Reader<'reader> {
+        reader::Reader::from_dir(self.dir.clone())
+    }
+
+    /// Whether a previous write failed to release the directory lock, see `lock::Dir::is_stuck`.
+    pub fn is_stuck(&self) -> bool {
+        self.dir.is_stuck()
+    }
+
+    /// Releases a directory lock that a previous write failed to release.
+    pub fn force_unlock(&self) -> Result<(), std::io::Error> {
+        self.dir.force_unlock()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fail_next_unlock(&self) {
+        self.dir.fail_next_unlock();
+    }
+
+    /// Makes every write durable before it returns, by syncing the written files and the
+    /// directories containing them to disk. Slower, so off by default.
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+}
+
+impl Writer for DirWriter {
+    fn apply(&self, tasks: &[BatchTask<String, String>]) -> Result<(), std::io::Error> {
+        self.batch(tasks)
+    }
+
+    fn try_apply(&self, tasks: &[BatchTask<String, String>]) -> Result<bool, std::io::Error> {
+        self.try_batch(tasks)
     }
 }
 
@@ -20,18 +74,8 @@ impl DirWriter {
     }
 
     pub fn remove<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), std::io::Error> {
-        self.0.batch(|root| {
-            let path = root.join(path);
-            if path.exists() {
-                if path.is_dir() {
-                    std::fs::remove_dir_all(path)
-                } else {
-                    std::fs::remove_file(path)
-                }
-            } else {
-                Ok(())
-            }
-        })?
+        self.dir
+            .batch(|root| remove(&root.join(path), self.fsync))?
     }
 
     pub fn batch<P, C>(&self, values: &[BatchTask<P, C>]) -> Result<(), std::io::Error>
@@ -39,32 +83,58 @@ impl DirWriter {
         P: AsRef<std::path::Path>,
         C: AsRef<[u8]>,
     {
-        self.0.batch(|root| {
-            for value in values {
-                match value {
-                    BatchTask::Write(path, contents) => {
-                        let path = root.join(path);
-                        if let Some(dir_path) = path.parent() {
-                            if !dir_path.exists() {
-                                std::fs::create_dir_all(dir_path)?;
-                            }
-                        };
-                        std::fs::write(path, contents)?;
-                    }
-                    BatchTask::Remove(path) => {
-                        let path = root.join(path);
-                        if path.exists() {
-                            if path.is_dir() {
-                                std::fs::remove_dir_all(path)?;
-                            } else {
-                                std::fs::remove_file(path)?;
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(())
-        })?
+        self.dir
+            .batch(|root| apply_batch(root, values, self.fsync))?
+    }
+
+    /// Like `batch`, but returns `false` without writing anything if the directory is locked.
+    pub fn try_batch<P, C>(&self, values: &[BatchTask<P, C>]) -> Result<bool, std::io::Error>
+    where
+        P: AsRef<std::path::Path>,
+        C: AsRef<[u8]>,
+    {
+        match self
+            .dir
+            .try_batch(|root| apply_batch(root, values, self.fsync))?
+        {
+            Some(result) => result.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Replaces the directory at `path` with a directory containing exactly `files`.
+    ///
+    /// Files are staged in a sibling `<path>.tmp` directory first and then renamed into place,
+    /// so readers observe either the previous contents or the new ones, never a mix of both.
+    /// The previous directory is moved aside to `<path>.old` while the new one is renamed into
+    /// place, so a crash in between leaves only `<path>.old`, which the next replace restores,
+    /// and which readers of `<path>` should fall back to until then.
+    pub fn replace_dir<P, N, C>(&self, path: P, files: &[(N, C)]) -> Result<(), std::io::Error>
+    where
+        P: AsRef<std::path::Path>,
+        N: AsRef<std::path::Path>,
+        C: AsRef<[u8]>,
+    {
+        self.dir
+            .batch(|root| replace_dir(&root.join(path), files, self.fsync, None))?
+    }
+
+    // like `replace_dir`, but fails after writing `interrupt_after` files, or, if that's all of
+    // them, between the two renames, as a crash would.
+    #[cfg(test)]
+    pub(crate) fn replace_dir_interrupted<P, N, C>(
+        &self,
+        path: P,
+        files: &[(N, C)],
+        interrupt_after: usize,
+    ) -> Result<(), std::io::Error>
+    where
+        P: AsRef<std::path::Path>,
+        N: AsRef<std::path::Path>,
+        C: AsRef<[u8]>,
+    {
+        self.dir
+            .batch(|root| replace_dir(&root.join(path), files, self.fsync, Some(interrupt_after)))?
     }
 
     pub fn write_usize(&self, path: &str, contents: &usize) -> Result<(), std::io::Error> {
@@ -82,11 +152,228 @@ impl DirWriter {
     pub fn write_string(&self, path: &str, contents: &str) -> Result<(), std::io::Error> {
         self.write(path, contents)
     }
+
+    /// Writes `contents` as is, for files that don't necessarily hold valid UTF-8.
+    pub fn write_bytes(&self, path: &str, contents: &[u8]) -> Result<(), std::io::Error> {
+        self.write(path, contents)
+    }
+}
+
+fn apply_batch<P, C>(
+    root: &std::path::Path,
+    values: &[BatchTask<P, C>],
+    fsync: bool,
+) -> Result<(), std::io::Error>
+where
+    P: AsRef<std::path::Path>,
+    C: AsRef<[u8]>,
+{
+    for value in values {
+        match value {
+            BatchTask::Write(path, contents) => {
+                let path = root.join(path);
+                create_parent_dir(&path)?;
+                std::fs::write(&path, contents).map_err(|e| annotate(e, "write", &path))?;
+                if fsync {
+                    sync_file(&path).map_err(|e| annotate(e, "sync", &path))?;
+                }
+            }
+            BatchTask::Append(path, contents) => {
+                let path = root.join(path);
+                create_parent_dir(&path)?;
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| std::io::Write::write_all(&mut file, contents.as_ref()))
+                    .map_err(|e| annotate(e, "append", &path))?;
+                if fsync {
+                    sync_file(&path).map_err(|e| annotate(e, "sync", &path))?;
+                }
+            }
+            BatchTask::Remove(path) => {
+                remove(&root.join(path), fsync)?;
+            }
+            BatchTask::ReplaceDir(path, files) => {
+                replace_dir(&root.join(path), files, fsync, None)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove(path: &std::path::Path, fsync: bool) -> Result<(), std::io::Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    if fsync {
+        sync_parent_dir(path)?;
+    }
+    Ok(())
+}
+
+// flushes the file's contents, and its entry in the parent directory, to disk.
+fn sync_file(path: &std::path::Path) -> Result<(), std::io::Error> {
+    std::fs::File::open(path)?.sync_all()?;
+    sync_parent_dir(path)
+}
+
+fn sync_parent_dir(path: &std::path::Path) -> Result<(), std::io::Error> {
+    path.parent().map_or(Ok(()), sync_dir)
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &std::path::Path) -> Result<(), std::io::Error> {
+    std::fs::File::open(path)?.sync_all()
+}
+
+// directories can't be opened, and so not synced, on other platforms.
+#[cfg(not(unix))]
+fn sync_dir(_path: &std::path::Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+fn create_parent_dir(path: &std::path::Path) -> Result<(), std::io::Error> {
+    match path.parent() {
+        Some(dir_path) if !dir_path.exists() => {
+            std::fs::create_dir_all(dir_path).map_err(|e| annotate(e, "create_dir", dir_path))
+        }
+        _ => Ok(()),
+    }
+}
+
+// keeps the kind of the error, but says which operation failed on which path.
+fn annotate(error: std::io::Error, operation: &str, path: &std::path::Path) -> std::io::Error {
+    std::io::Error::new(
+        error.kind(),
+        format!("{} {}: {}", operation, path.display(), error),
+    )
+}
+
+fn replace_dir<N, C>(
+    path: &std::path::Path,
+    files: &[(N, C)],
+    fsync: bool,
+    interrupt_after: Option<usize>,
+) -> Result<(), std::io::Error>
+where
+    N: AsRef<std::path::Path>,
+    C: AsRef<[u8]>,
+{
+    let tmp_path = path.with_extension("tmp");
+    let old_path = path.with_extension("old");
+
+    // a previous replace might have been interrupted between the two renames below
+    if !path.exists() && old_path.exists() {
+        std::fs::rename(&old_path, path)?;
+    }
+
+    if tmp_path.exists() {
+        std::fs::remove_dir_all(&tmp_path)?;
+    }
+    std::fs::create_dir_all(&tmp_path)?;
+
+    for (i, (name, contents)) in files.iter().enumerate() {
+        if interrupt_after == Some(i) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("{}: interrupted after {} files", path.display(), i),
+            ));
+        }
+        std::fs::write(tmp_path.join(name), contents)?;
+        if fsync {
+            std::fs::File::open(tmp_path.join(name))?.sync_all()?;
+        }
+    }
+    if fsync {
+        sync_dir(&tmp_path)?;
+    }
+
+    if path.exists() {
+        if old_path.exists() {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+        std::fs::rename(path, &old_path)?;
+        if interrupt_after == Some(files.len()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("{}: interrupted between renames", path.display()),
+            ));
+        }
+        std::fs::rename(&tmp_path, path)?;
+        std::fs::remove_dir_all(&old_path)?;
+    } else {
+        std::fs::rename(&tmp_path, path)?;
+    }
+    if fsync {
+        sync_parent_dir(path)?;
+    }
+
+    Ok(())
+}
+
+/// Keeps written files in memory, for tests that don't need to touch the filesystem.
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryWriter {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<std::path::PathBuf, String>>>,
+}
+
+#[cfg(test)]
+impl InMemoryWriter {
+    pub fn files(&self) -> std::collections::BTreeMap<std::path::PathBuf, String> {
+        self.files.lock().unwrap().clone()
+    }
+
+    /// A reader of the files written, including those written after it's created.
+    pub fn reader<'reader>(&self) -> reader::Reader<'reader> {
+        reader::Reader::Memory(reader::MemoryReader(std::sync::Arc::clone(&self.files)))
+    }
+}
+
+#[cfg(test)]
+impl Writer for InMemoryWriter {
+    fn apply(&self, tasks: &[BatchTask<String, String>]) -> Result<(), std::io::Error> {
+        let mut files = self.files.lock().unwrap();
+        for task in tasks {
+            match task {
+                BatchTask::Write(path, contents) => {
+                    files.insert(path.into(), contents.clone());
+                }
+                BatchTask::Append(path, contents) => {
+                    files.entry(path.into()).or_default().push_str(contents);
+                }
+                BatchTask::Remove(path) => {
+                    files.retain(|file_path, _| !file_path.starts_with(path));
+                }
+                BatchTask::ReplaceDir(path, dir_files) => {
+                    files.retain(|file_path, _| !file_path.starts_with(path));
+                    for (name, contents) in dir_files {
+                        files.insert(std::path::Path::new(path).join(name), contents.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_apply(&self, tasks: &[BatchTask<String, String>]) -> Result<bool, std::io::Error> {
+        self.apply(tasks).map(|()| true)
+    }
 }
 
 pub enum BatchTask<P: AsRef<std::path::Path>, C: AsRef<[u8]>> {
     Write(P, C),
+    /// Appends to the file, creating it if needed.
+    Append(P, C),
     Remove(P),
+    /// Atomically replaces the directory with one containing exactly the given files.
+    ReplaceDir(P, Vec<(P, C)>),
 }
 
 #[cfg(test)]
@@ -104,6 +391,90 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_bytes() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+        writer.write_bytes("foo/bar", b"\xff\xfe").unwrap();
+        assert_eq!(
+            std::fs::read(root.path().join("foo/bar")).unwrap(),
+            b"\xff\xfe"
+        );
+    }
+
+    #[test]
+    fn test_write_string_missing_parent() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+        writer.write_string("a/b/c", "value").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a/b/c")).unwrap(),
+            "value"
+        );
+    }
+
+    #[test]
+    fn test_write_string_errors() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+
+        // the parent can't be created, as it's a file
+        writer.write_string("file", "value").unwrap();
+        let error = writer.write_string("file/dir/child", "value").unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "create_dir {}",
+            root.path().join("file/dir").display()
+        )));
+
+        // the file can't be written, as it's a directory
+        writer.write_string("dir/child", "value").unwrap();
+        let error = writer.write_string("dir", "value").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("write {}", root.path().join("dir").display())));
+    }
+
+    #[test]
+    fn test_fsync() {
+        for fsync in [false, true] {
+            let root = tempfile::tempdir().unwrap();
+            let writer = DirWriter::open(root.path()).unwrap().with_fsync(fsync);
+            writer
+                .batch(&[
+                    BatchTask::Write("dir/file", "one"),
+                    BatchTask::Append("dir/log", "two"),
+                    BatchTask::ReplaceDir("replaced", vec![("file", "three")]),
+                    BatchTask::Remove("dir/file"),
+                ])
+                .unwrap();
+            assert!(!root.path().join("dir/file").exists());
+            assert_eq!(
+                std::fs::read_to_string(root.path().join("dir/log")).unwrap(),
+                "two"
+            );
+            assert_eq!(
+                std::fs::read_to_string(root.path().join("replaced/file")).unwrap(),
+                "three"
+            );
+        }
+    }
+
+    #[test]
+    fn test_append() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+        writer
+            .batch(&[
+                BatchTask::Append("dir/file", "one\n"),
+                BatchTask::Append("dir/file", "two\n"),
+            ])
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/file")).unwrap(),
+            "one\ntwo\n"
+        );
+    }
+
     #[test]
     fn test_remove() {
         let root = tempfile::tempdir().unwrap();
@@ -117,4 +488,63 @@ mod tests {
         writer.remove("parent").unwrap();
         assert!(!root.path().join("parent").exists());
     }
+
+    #[test]
+    fn test_replace_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+        writer.write("dir/stale", b"stale").unwrap();
+        writer
+            .replace_dir("dir", &[("foo", "1"), ("bar", "2")])
+            .unwrap();
+        assert!(!root.path().join("dir/stale").exists());
+        assert!(!root.path().join("dir.tmp").exists());
+        assert!(!root.path().join("dir.old").exists());
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/foo")).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/bar")).unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_replace_dir_interrupted() {
+        let root = tempfile::tempdir().unwrap();
+        let writer = DirWriter::open(root.path()).unwrap();
+        writer
+            .replace_dir("dir", &[("foo", "1"), ("bar", "2")])
+            .unwrap();
+        writer
+            .replace_dir_interrupted("dir", &[("foo", "3"), ("bar", "4")], 1)
+            .unwrap_err();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/foo")).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/bar")).unwrap(),
+            "2"
+        );
+
+        // between the renames, only the previous directory is left, moved aside
+        writer
+            .replace_dir_interrupted("dir", &[("foo", "3"), ("bar", "4")], 2)
+            .unwrap_err();
+        assert!(!root.path().join("dir").exists());
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir.old/foo")).unwrap(),
+            "1"
+        );
+
+        // which the next replace restores before replacing it
+        writer.replace_dir("dir", &[("foo", "5")]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("dir/foo")).unwrap(),
+            "5"
+        );
+        assert!(!root.path().join("dir.old").exists());
+    }
 }